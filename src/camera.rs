@@ -0,0 +1,373 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::gravity::Gravity;
+use crate::input::Action;
+use crate::ldtk::LevelBounds;
+use crate::ldtk::TILE_SCALE;
+use crate::player::Player;
+use crate::screen::{PixelScale, VIEW_HEIGHT, VIEW_WIDTH};
+use crate::{MainCamera, METERS_TO_PIXELS};
+
+// Moving further than this in one frame is a teleport (respawning or
+// changing levels), which the camera jumps to instead of panning, in meters
+const TELEPORT_DISTANCE: f32 = 5.;
+
+// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.2;
+
+// How far and how much the camera shakes at full trauma, in meters and
+// radians, and how fast
+const MAX_SHAKE_OFFSET: f32 = 1.5;
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+const SHAKE_FREQUENCY: f32 = 25.;
+
+// The shake intensities CameraShake steps through
+const SHAKE_INTENSITIES: &[f32] = &[1., 0.5, 0.];
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum CameraSystem {
+    Follow,
+    Trauma,
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(CameraSettings::default())
+            .insert_resource(CameraShake::default())
+            .add_event::<Shake>()
+            .add_system(align_camera.system().before(CameraSystem::Follow))
+            .add_system(shake_intensity.system().before(CameraSystem::Follow))
+            .add_system(follow_player.system().label(CameraSystem::Follow))
+            .add_system(add_trauma.system().label(CameraSystem::Trauma))
+            .add_system(
+                shake_camera
+                    .system()
+                    .after(CameraSystem::Follow)
+                    .after(CameraSystem::Trauma),
+            );
+    }
+}
+
+pub struct CameraSettings {
+    // Keep the camera upright instead of turning it with gravity, for
+    // players prone to motion sickness. Toggled with AlignCamera.
+    pub world_aligned: bool,
+    // Half the size of the box around the camera's focus that the player
+    // moves in without moving the camera, in meters
+    pub deadzone: Vec2,
+    // Roughly how many seconds the camera takes to catch up
+    pub smooth_time: f32,
+    // How many seconds ahead of the player's sideways velocity the camera
+    // looks, and how far it can look in meters
+    pub lookahead_time: f32,
+    pub lookahead_distance: f32,
+    // Scales every shake, zero turns shaking off. Stepped through
+    // SHAKE_INTENSITIES with CameraShake.
+    pub shake_intensity: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            world_aligned: false,
+            deadzone: Vec2::new(2., 3.),
+            smooth_time: 0.2,
+            lookahead_time: 0.15,
+            lookahead_distance: 6.,
+            shake_intensity: 1.,
+        }
+    }
+}
+
+// Where the camera is heading, in pixels, and where following the player
+// put it before any shaking. Lives on the MainCamera.
+pub struct CameraFollow {
+    focus: Vec2,
+    velocity: Vec2,
+    last_player: Option<Vec2>,
+    position: Vec2,
+    rotation: Quat,
+    zoom: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        CameraFollow {
+            focus: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+            last_player: None,
+            position: Vec2::ZERO,
+            rotation: Quat::IDENTITY,
+            zoom: 1.,
+        }
+    }
+}
+
+// A rectangle of the level, in pixels, that holds the camera while the
+// player is inside it. Zooms above 1 make everything bigger, rounded to
+// keep each tileset pixel a whole number of screen pixels.
+pub struct CameraRoom {
+    pub center: Vec2,
+    pub half_extents: Vec2,
+    pub zoom: f32,
+}
+
+// Send this event to shake the camera. The value is the trauma to add, up
+// to a total of 1.
+pub struct Shake(pub f32);
+
+// How shaken the camera is. The shake grows with the square of trauma, so
+// small bumps stay subtle.
+#[derive(Default)]
+pub struct CameraShake {
+    pub trauma: f32,
+    time: f32,
+}
+
+// Move current towards target like a critically damped spring that takes
+// about smooth_time to get there, without ever overshooting.
+fn smooth_damp(
+    current: Vec2,
+    target: Vec2,
+    velocity: &mut Vec2,
+    smooth_time: f32,
+    dt: f32,
+) -> Vec2 {
+    let omega = 2. / smooth_time.max(f32::EPSILON);
+    let x = omega * dt;
+    let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + omega * change) * dt;
+    *velocity = (*velocity - omega * temp) * decay;
+    target + (change + temp) * decay
+}
+
+// Keep the view inside a rectangle, centering it on any axis where the
+// rectangle is smaller than the view.
+fn clamp_to_bounds(
+    position: Vec2,
+    view: Vec2,
+    center: Vec2,
+    half_extents: Vec2,
+) -> Vec2 {
+    let room = (half_extents - view).max(Vec2::ZERO);
+    center + (position - center).clamp(-room, room)
+}
+
+// The closest zoom that still shows every tileset pixel as a whole number
+// of screen pixels
+fn pixel_zoom(zoom: f32, pixel_scale: f32) -> f32 {
+    (zoom * pixel_scale).round().max(1.) / pixel_scale
+}
+
+// Round a camera position to the screen pixels of a view turned by
+// rotation, so the tiles don't shimmer as it moves
+fn snap_to_pixels(position: Vec2, rotation: Quat, pixel: f32) -> Vec2 {
+    let local = rotation.inverse() * position.extend(0.);
+    let snapped = (local.truncate() / pixel).round() * pixel;
+    (rotation * snapped.extend(0.)).truncate()
+}
+
+type FollowQuery<'a> = (
+    &'a RigidBodyPosition,
+    &'a RigidBodyVelocity,
+    &'a Gravity,
+    &'a Transform,
+);
+
+type CameraQuery<'a> = (&'a mut Transform, &'a mut CameraFollow);
+
+// Both read the Transform, the player's and the camera's
+type FollowQuerySet<'a> = QuerySet<(
+    Query<'a, FollowQuery<'static>, With<Player>>,
+    Query<'a, CameraQuery<'static>, With<MainCamera>>,
+)>;
+
+// Follow the player with a deadzone, look ahead along where they are going
+// and keep the view inside the camera room the player is in, or else the
+// level. Moving between rooms pans and zooms over smooth_time.
+pub fn follow_player(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    pixel_scale: Res<PixelScale>,
+    bounds: Option<Res<LevelBounds>>,
+    rooms: Query<&CameraRoom>,
+    mut query: FollowQuerySet,
+) {
+    let (player, velocity, rotation) = match query.q0().single() {
+        Ok((position, velocity, gravity, transform)) => {
+            let local = gravity.basis.transpose() * velocity.linvel;
+            let right = gravity.basis * Vector::new(1., 0.);
+            (
+                Vec2::new(
+                    position.position.translation.x,
+                    position.position.translation.y,
+                ),
+                Vec2::new(right.x, right.y) * local.x,
+                if settings.world_aligned {
+                    Quat::IDENTITY
+                } else {
+                    transform.rotation
+                },
+            )
+        }
+        Err(_) => return,
+    };
+
+    let (mut transform, mut follow) = match query.q1_mut().single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let player = player * METERS_TO_PIXELS;
+    let teleported = match follow.last_player {
+        Some(last) => {
+            last.distance(player) > TELEPORT_DISTANCE * METERS_TO_PIXELS
+        }
+        None => true,
+    };
+    follow.last_player = Some(player);
+
+    // The deadzone turns with the camera so that it stays a box on screen
+    let to_screen = rotation.inverse();
+    let offset = to_screen * (player - follow.focus).extend(0.);
+    let deadzone = settings.deadzone * METERS_TO_PIXELS;
+    let push = offset.truncate() - offset.truncate().clamp(-deadzone, deadzone);
+    follow.focus += (rotation * push.extend(0.)).truncate();
+
+    let lookahead = (velocity * settings.lookahead_time)
+        .clamp_length_max(settings.lookahead_distance)
+        * METERS_TO_PIXELS;
+    let mut target = follow.focus + lookahead;
+
+    let room = rooms.iter().find(|room| {
+        let offset = (player - room.center).abs();
+        offset.x <= room.half_extents.x && offset.y <= room.half_extents.y
+    });
+    let frame = match (room, bounds.as_ref()) {
+        (Some(room), _) => Some((room.center, room.half_extents)),
+        (None, Some(bounds)) => Some((Vec2::ZERO, bounds.half_extents)),
+        (None, None) => None,
+    };
+
+    let zoom = room.map(|room| room.zoom).unwrap_or(1.);
+    follow.zoom = if teleported {
+        zoom
+    } else {
+        let blend = 1. - (-time.delta_seconds() / settings.smooth_time).exp();
+        follow.zoom + (zoom - follow.zoom) * blend
+    };
+    let shown_zoom = pixel_zoom(follow.zoom, pixel_scale.0);
+
+    // The part of the world the view covers on each axis once it's turned
+    let (axis, angle) = rotation.to_axis_angle();
+    let angle = angle * axis.z.signum();
+    let (sin, cos) = (angle.sin().abs(), angle.cos().abs());
+    let half = Vec2::new(VIEW_WIDTH, VIEW_HEIGHT) / 2. / shown_zoom;
+    let view =
+        Vec2::new(cos * half.x + sin * half.y, sin * half.x + cos * half.y);
+    if let Some((center, half_extents)) = frame {
+        target = clamp_to_bounds(target, view, center, half_extents);
+    }
+
+    let position = if teleported {
+        follow.focus = player;
+        follow.velocity = Vec2::ZERO;
+        match frame {
+            Some((center, half_extents)) => {
+                clamp_to_bounds(player, view, center, half_extents)
+            }
+            None => player,
+        }
+    } else {
+        smooth_damp(
+            follow.position,
+            target,
+            &mut follow.velocity,
+            settings.smooth_time,
+            time.delta_seconds(),
+        )
+    };
+
+    follow.position = position;
+    follow.rotation = rotation;
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+    transform.rotation = rotation;
+    transform.scale = Vec3::new(1. / shown_zoom, 1. / shown_zoom, 1.);
+}
+
+fn align_camera(
+    actions: Res<Input<Action>>,
+    mut settings: ResMut<CameraSettings>,
+) {
+    if actions.just_pressed(Action::AlignCamera) {
+        settings.world_aligned = !settings.world_aligned;
+    }
+}
+
+fn shake_intensity(
+    actions: Res<Input<Action>>,
+    mut settings: ResMut<CameraSettings>,
+) {
+    if actions.just_pressed(Action::CameraShake) {
+        let next = SHAKE_INTENSITIES
+            .iter()
+            .position(|intensity| *intensity == settings.shake_intensity)
+            .map_or(0, |index| (index + 1) % SHAKE_INTENSITIES.len());
+        settings.shake_intensity = SHAKE_INTENSITIES[next];
+        println!("Camera shake: {}", settings.shake_intensity);
+    }
+}
+
+fn add_trauma(mut events: EventReader<Shake>, mut shake: ResMut<CameraShake>) {
+    for Shake(trauma) in events.iter() {
+        shake.trauma = (shake.trauma + trauma).min(1.);
+    }
+}
+
+// Smooth noise between -1 and 1 that looks random enough for shaking,
+// different for each seed.
+fn wobble(time: f32, seed: f32) -> f32 {
+    ((time + seed * 1.7).sin() + (time * 2.3 + seed * 3.1).sin() * 0.5) / 1.5
+}
+
+// Shake the camera around where following the player put it, letting the
+// trauma wear off, and line it up with the screen pixels.
+pub fn shake_camera(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    pixel_scale: Res<PixelScale>,
+    mut shake: ResMut<CameraShake>,
+    mut query: Query<(&mut Transform, &CameraFollow), With<MainCamera>>,
+) {
+    let delta = time.delta_seconds();
+    shake.trauma = (shake.trauma - TRAUMA_DECAY * delta).max(0.);
+    shake.time += delta * SHAKE_FREQUENCY;
+
+    let amount = shake.trauma * shake.trauma * settings.shake_intensity;
+    let t = shake.time;
+    let offset = Vec2::new(wobble(t, 0.), wobble(t, 1.))
+        * MAX_SHAKE_OFFSET
+        * METERS_TO_PIXELS
+        * amount;
+    let angle = wobble(t, 2.) * MAX_SHAKE_ANGLE * amount;
+
+    for (mut transform, follow) in query.iter_mut() {
+        let rotation = follow.rotation * Quat::from_rotation_z(angle);
+        let offset = follow.rotation * offset.extend(0.);
+        let pixel = TILE_SCALE
+            / (pixel_scale.0 * pixel_zoom(follow.zoom, pixel_scale.0));
+        let position = snap_to_pixels(
+            follow.position + offset.truncate(),
+            rotation,
+            pixel,
+        );
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        transform.rotation = rotation;
+    }
+}
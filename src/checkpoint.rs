@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::dash::{Dash, DashEvent};
+use crate::death::Dying;
+use crate::gravity::{GlobalGravity, Gravity};
+use crate::input::Action;
+use crate::ldtk::{ChangeLevel, LevelEntry, LevelTarget, Map};
+use crate::player::{Jump, MoveToStart, Physics, Player, PlayerStart};
+
+// A place in a level that the player comes back to after dying. Checkpoints
+// without a rotation keep the way gravity pointed when they were reached.
+pub struct Checkpoint {
+    pub position: Vector<f32>,
+    pub rotation: Option<f32>,
+}
+
+// Where the player comes back to: the last checkpoint reached, the start of
+// the level they last started, or where they walked into the level they
+// last crossed into. The level is None until one starts.
+#[derive(Default)]
+pub struct RespawnPoint {
+    pub position: Vector<f32>,
+    pub rotation: f32,
+    pub level: Option<usize>,
+}
+
+// Send this event to put the player back at the RespawnPoint.
+pub struct Respawn;
+
+// Starting a level makes its PlayerStart the respawn point.
+pub fn start_checkpoint(
+    mut events: EventReader<MoveToStart>,
+    start: Res<PlayerStart>,
+    map: Option<Res<Map>>,
+    mut point: ResMut<RespawnPoint>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    *point = RespawnPoint {
+        position: start.position,
+        rotation: start.rotation,
+        level: map.map(|map| map.current_level()),
+    };
+}
+
+// Make a checkpoint the respawn point when the player touches it.
+pub fn reach_checkpoint(
+    mut events: EventReader<IntersectionEvent>,
+    map: Option<Res<Map>>,
+    mut point: ResMut<RespawnPoint>,
+    checkpoints: Query<&Checkpoint>,
+    players: Query<&Gravity, With<Player>>,
+) {
+    for event in events.iter().filter(|event| event.intersecting) {
+        let a = event.collider1.entity();
+        let b = event.collider2.entity();
+
+        for (checkpoint, other) in [(a, b), (b, a)].iter() {
+            if let (Ok(checkpoint), Ok(gravity)) =
+                (checkpoints.get(*checkpoint), players.get(*other))
+            {
+                *point = RespawnPoint {
+                    position: checkpoint.position,
+                    rotation: checkpoint
+                        .rotation
+                        .unwrap_or(gravity.target_angle),
+                    level: map.as_ref().map(|map| map.current_level()),
+                };
+            }
+        }
+    }
+}
+
+// Respawn when the restart key is pressed, unless the player is dying and
+// about to respawn anyway.
+pub fn restart(
+    actions: Res<Input<Action>>,
+    dying: Query<(), (With<Player>, With<Dying>)>,
+    mut respawn: EventWriter<Respawn>,
+) {
+    if actions.just_pressed(Action::Restart) && dying.iter().next().is_none() {
+        respawn.send(Respawn);
+    }
+}
+
+type RespawnQuery<'a> = (
+    Entity,
+    &'a Physics,
+    &'a mut RigidBodyPosition,
+    &'a mut RigidBodyVelocity,
+    &'a mut Gravity,
+    &'a mut Jump,
+    &'a mut Dash,
+);
+
+// Put the player back at the respawn point, standing still with gravity
+// pointing the way it did there. Respawn points in another level change
+// to that level without moving the player again.
+pub fn respawn(
+    mut events: EventReader<Respawn>,
+    point: Res<RespawnPoint>,
+    map: Option<Res<Map>>,
+    mut global: ResMut<GlobalGravity>,
+    mut change_level: EventWriter<ChangeLevel>,
+    mut dash_events: EventWriter<DashEvent>,
+    mut query: Query<RespawnQuery, With<Player>>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    if let (Some(map), Some(level)) = (map, point.level) {
+        if map.current_level() != level {
+            change_level.send(ChangeLevel {
+                level: LevelTarget::Index(level),
+                entry: LevelEntry::Stay,
+            });
+        }
+    }
+
+    global.gravity.set_angle(point.rotation);
+
+    for (
+        entity,
+        physics,
+        mut position,
+        mut velocity,
+        mut gravity,
+        mut jump,
+        mut dash,
+    ) in query.iter_mut()
+    {
+        position.position = point.position.into();
+        velocity.linvel = [0., 0.].into();
+        velocity.angvel = 0.;
+
+        let mut fresh = Gravity::new(physics.gravity);
+        fresh.rotation_duration = gravity.rotation_duration;
+        fresh.set_angle(point.rotation);
+        *gravity = fresh;
+
+        *jump = Jump::default();
+        if dash.dashing() {
+            dash_events.send(DashEvent::End(entity));
+        }
+        *dash = Dash::default();
+    }
+}
@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::gravity::Gravity;
+use crate::kinematic::Kinematic;
+use crate::player::DEFAULT_MAX_SLOPE;
+
+// A contact counts as ceiling or wall when its normal is within 45 degrees
+// of that direction in the gravity basis
+const CONTACT_NORMAL_MIN: f32 = 0.7;
+
+// What a body with Gravity is touching, relative to the current basis.
+// Walls are named by the side of the body they are on.
+pub struct Contacts {
+    // Radians from up of the steepest floor, steeper ones are walls
+    pub max_slope: f32,
+
+    // World space normal of the floor, pointing away from it
+    pub floor_normal: Option<Vector<f32>>,
+    pub wall_left: bool,
+    pub wall_right: bool,
+    pub ceiling: bool,
+}
+
+impl Default for Contacts {
+    fn default() -> Self {
+        Contacts {
+            max_slope: DEFAULT_MAX_SLOPE,
+            floor_normal: None,
+            wall_left: false,
+            wall_right: false,
+            ceiling: false,
+        }
+    }
+}
+
+impl Contacts {
+    pub fn grounded(&self) -> bool {
+        self.floor_normal.is_some()
+    }
+
+    // Record a contact from its world space normal, pointing towards the
+    // body. The floor is the flattest one found, a walkable normal that is
+    // not the flattest is never a wall.
+    pub fn add_normal(&mut self, basis: &Matrix<f32>, normal: Vector<f32>) {
+        // The basis is a rotation so its transpose maps world space into
+        // [right, up] coordinates
+        let local = basis.transpose() * normal;
+
+        if local.y >= self.max_slope.cos() {
+            let flatter = match self.floor_normal {
+                Some(floor) => local.y > (basis.transpose() * floor).y,
+                None => true,
+            };
+            if flatter {
+                self.floor_normal = Some(normal);
+            }
+        } else if local.y < -CONTACT_NORMAL_MIN {
+            self.ceiling = true;
+        } else if local.x > CONTACT_NORMAL_MIN {
+            self.wall_left = true;
+        } else if local.x < -CONTACT_NORMAL_MIN {
+            self.wall_right = true;
+        }
+    }
+}
+
+// Whether a collider blocks a body's shape casts, skipping the body's own
+// collider and sensors.
+pub fn is_solid(
+    own: ColliderHandle,
+    other: ColliderHandle,
+    collider_types: &Query<&ColliderType>,
+) -> bool {
+    other != own
+        && !matches!(
+            collider_types.get(other.entity()),
+            Ok(ColliderType::Sensor)
+        )
+}
+
+// Classify the active contacts of every dynamic body with Gravity. The
+// normals are taken from Rapier's narrow phase, so they lag the physics
+// step by a frame. Kinematic bodies find their own contacts while moving.
+pub fn detect_contacts(
+    narrow_phase: Res<NarrowPhase>,
+    mut query: Query<(Entity, &Gravity, &mut Contacts), Without<Kinematic>>,
+) {
+    for (entity, gravity, mut contacts) in query.iter_mut() {
+        let handle = entity.handle();
+        let mut next = Contacts {
+            max_slope: contacts.max_slope,
+            ..Default::default()
+        };
+
+        let normals = narrow_phase
+            .contacts_with(handle)
+            .filter(|pair| pair.has_any_active_contact)
+            .flat_map(|pair| {
+                // Manifold normals point away from collider1, flip them so
+                // they always point towards this body
+                let sign = if pair.collider1 == handle { -1. } else { 1. };
+                pair.manifolds
+                    .iter()
+                    .filter(|manifold| {
+                        !manifold.data.solver_contacts.is_empty()
+                    })
+                    .map(move |manifold| manifold.data.normal * sign)
+            });
+
+        for normal in normals {
+            next.add_normal(&gravity.basis, normal);
+        }
+
+        *contacts = next;
+    }
+}
@@ -0,0 +1,156 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::camera::Shake;
+use crate::contact::Contacts;
+use crate::gravity::Gravity;
+use crate::input::Action;
+use crate::player::{Physics, Player, PLAYER_COLOR};
+
+const DASH_COLOR: Color = Color::rgb(0.7, 0.8, 1.);
+
+// Camera trauma from starting a dash
+const DASH_TRAUMA: f32 = 0.2;
+
+// Sent when a dash starts and ends for effects and sound, the Dash
+// component of the entity has the direction.
+pub enum DashEvent {
+    Start(Entity),
+    End(Entity),
+}
+
+// The state of the player's dash. Directions are [right, up] in the
+// player's gravity basis.
+pub struct Dash {
+    // Seconds left in the current dash, zero when not dashing
+    pub remaining: f32,
+    // Seconds left before another dash can start
+    pub cooldown: f32,
+    // Dashes left before touching the ground again
+    pub charges: u32,
+    pub direction: Vector<f32>,
+    // Which way the player last moved, used when no direction is held
+    pub facing: f32,
+}
+
+impl Default for Dash {
+    fn default() -> Self {
+        Dash {
+            remaining: 0.,
+            cooldown: 0.,
+            charges: 0,
+            direction: [1., 0.].into(),
+            facing: 1.,
+        }
+    }
+}
+
+impl Dash {
+    pub fn dashing(&self) -> bool {
+        self.remaining > 0.
+    }
+}
+
+type DashQuery<'a> = (
+    Entity,
+    &'a Physics,
+    &'a Contacts,
+    &'a mut Gravity,
+    &'a mut Dash,
+    &'a mut RigidBodyVelocity,
+);
+
+// Shift dashes towards the held direction keys (WASD), in eight
+// directions. Gravity is suspended while dashing.
+pub fn dash(
+    time: Res<Time>,
+    actions: Res<Input<Action>>,
+    mut events: EventWriter<DashEvent>,
+    mut shake: EventWriter<Shake>,
+    mut query: Query<DashQuery, With<Player>>,
+) {
+    let delta = time.delta_seconds();
+
+    for (entity, physics, contacts, mut gravity, mut dash, mut velocity) in
+        query.iter_mut()
+    {
+        let mut input: Vector<f32> = [0., 0.].into();
+        if actions.pressed(Action::MoveLeft) {
+            input.x -= 1.;
+        }
+        if actions.pressed(Action::MoveRight) {
+            input.x += 1.;
+        }
+        if actions.pressed(Action::MoveDown) {
+            input.y -= 1.;
+        }
+        if actions.pressed(Action::MoveUp) {
+            input.y += 1.;
+        }
+        if input.x != 0. {
+            dash.facing = input.x;
+        }
+
+        if dash.dashing() {
+            dash.remaining -= delta;
+            if dash.dashing() {
+                velocity.linvel =
+                    gravity.basis * dash.direction * physics.dash_velocity;
+            } else {
+                // Leave the dash at walking speed
+                velocity.linvel =
+                    gravity.basis * dash.direction * physics.velocity;
+                gravity.suspended = false;
+                dash.cooldown = physics.dash_cooldown;
+                events.send(DashEvent::End(entity));
+            }
+            continue;
+        }
+
+        dash.cooldown = (dash.cooldown - delta).max(0.);
+        if contacts.grounded() {
+            dash.charges = physics.dash_charges;
+        }
+
+        if !actions.just_pressed(Action::Dash)
+            || dash.cooldown > 0.
+            || dash.charges == 0
+        {
+            continue;
+        }
+
+        dash.direction = if input.x == 0. && input.y == 0. {
+            [dash.facing, 0.].into()
+        } else {
+            input.normalize()
+        };
+        dash.remaining = physics.dash_duration;
+        dash.charges -= 1;
+        gravity.suspended = true;
+        velocity.linvel =
+            gravity.basis * dash.direction * physics.dash_velocity;
+        events.send(DashEvent::Start(entity));
+        shake.send(Shake(DASH_TRAUMA));
+    }
+}
+
+// Light the player up while it dashes
+pub fn dash_color(
+    mut events: EventReader<DashEvent>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<&Handle<ColorMaterial>>,
+) {
+    for event in events.iter() {
+        let (entity, color) = match event {
+            DashEvent::Start(entity) => (entity, DASH_COLOR),
+            DashEvent::End(entity) => (entity, PLAYER_COLOR),
+        };
+        if let Some(material) = query
+            .get(*entity)
+            .ok()
+            .and_then(|handle| materials.get_mut(handle))
+        {
+            material.color = color;
+        }
+    }
+}
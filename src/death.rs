@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::camera::Shake;
+use crate::checkpoint::Respawn;
+use crate::gravity::Gravity;
+use crate::player::{Player, BOUNDARY};
+use crate::tuning::PlayerTuning;
+
+// Seconds the player stays frozen, shrinking away, before respawning
+const DEATH_FREEZE: f32 = 0.5;
+
+// Camera trauma from dying
+const DEATH_TRAUMA: f32 = 0.6;
+
+// Marks a collider that kills the player when touched.
+pub struct Hazard;
+
+// Send this event to kill the player.
+pub struct Death;
+
+// How many times the player has died.
+#[derive(Default)]
+pub struct DeathCount(pub u32);
+
+// A dead player waiting to respawn. Its body is static until then.
+pub struct Dying {
+    remaining: f32,
+}
+
+// Kill the player when they touch a hazard.
+pub fn touch_hazard(
+    mut events: EventReader<IntersectionEvent>,
+    mut death: EventWriter<Death>,
+    hazards: Query<(), With<Hazard>>,
+    players: Query<(), (With<Player>, Without<Dying>)>,
+) {
+    for event in events.iter().filter(|event| event.intersecting) {
+        let a = event.collider1.entity();
+        let b = event.collider2.entity();
+
+        for (hazard, other) in [(a, b), (b, a)].iter() {
+            if let (Ok(_), Ok(_)) = (hazards.get(*hazard), players.get(*other))
+            {
+                death.send(Death);
+            }
+        }
+    }
+}
+
+// Kill the player when they fall out of the world.
+pub fn out_of_bounds(
+    mut death: EventWriter<Death>,
+    query: Query<&RigidBodyPosition, (With<Player>, Without<Dying>)>,
+) {
+    for position in query.iter() {
+        let magnitude = position.position.translation.vector.magnitude();
+
+        if magnitude > BOUNDARY {
+            death.send(Death);
+        }
+    }
+}
+
+type DieQuery<'a> = (
+    Entity,
+    &'a mut RigidBodyType,
+    &'a mut RigidBodyVelocity,
+    &'a mut Gravity,
+);
+
+// Count the death and freeze the player where they died.
+pub fn die(
+    mut commands: Commands,
+    mut events: EventReader<Death>,
+    mut count: ResMut<DeathCount>,
+    mut shake: EventWriter<Shake>,
+    mut query: Query<DieQuery, (With<Player>, Without<Dying>)>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for (entity, mut body_type, mut velocity, mut gravity) in query.iter_mut() {
+        count.0 += 1;
+        shake.send(Shake(DEATH_TRAUMA));
+        *body_type = RigidBodyType::Static;
+        velocity.linvel = [0., 0.].into();
+        velocity.angvel = 0.;
+        gravity.suspended = true;
+        commands.entity(entity).insert(Dying {
+            remaining: DEATH_FREEZE,
+        });
+    }
+}
+
+// Shrink the dead player away, then give its body back and respawn.
+pub fn dying(
+    mut commands: Commands,
+    time: Res<Time>,
+    tuning: Res<PlayerTuning>,
+    mut respawn: EventWriter<Respawn>,
+    mut query: Query<(Entity, &mut Dying, &mut RigidBodyType, &mut Transform)>,
+) {
+    for (entity, mut dying, mut body_type, mut transform) in query.iter_mut() {
+        dying.remaining -= time.delta_seconds();
+        transform.scale = Vec3::splat((dying.remaining / DEATH_FREEZE).max(0.));
+
+        if dying.remaining <= 0. {
+            *body_type = tuning.controller.body_type();
+            transform.scale = Vec3::ONE;
+            commands.entity(entity).remove::<Dying>();
+            respawn.send(Respawn);
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use bevy::prelude::*;
+use std::fs;
+
+use crate::death::DeathCount;
+use crate::input::Action;
+use crate::ldtk::asset_path;
+use crate::player::{Physics, Player};
+use crate::tuning::{PlayerTuning, TuningHandle, TUNING_FILE_PATH};
+
+pub const FONT_PATH: &str = "fonts/DejaVuSansMono.ttf";
+const FONT_SIZE: f32 = 16.;
+
+// The Physics fields the panel can edit and how much one key press
+// changes them
+const FIELDS: &[(&str, f32)] = &[
+    ("velocity", 1.),
+    ("jump_distance", 1.),
+    ("jump_height", 1.),
+    ("heavy_scalar", 0.1),
+];
+
+// DebugPanel shows the panel, DebugUp/DebugDown select a field,
+// DebugDecrease/DebugIncrease change it and DebugSave writes the current
+// values to the tuning file.
+#[derive(Default)]
+pub struct DebugPanel {
+    pub visible: bool,
+    pub selected: usize,
+}
+
+pub struct DebugText;
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(DebugPanel::default())
+            .add_startup_system(setup.system())
+            .add_system(edit_physics.system())
+            .add_system(draw_panel.system());
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: FONT_SIZE,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(DebugText);
+}
+
+fn field_mut<'a>(
+    index: usize,
+    velocity: &'a mut f32,
+    jump_distance: &'a mut f32,
+    jump_height: &'a mut f32,
+    heavy_scalar: &'a mut f32,
+) -> &'a mut f32 {
+    match index {
+        0 => velocity,
+        1 => jump_distance,
+        2 => jump_height,
+        _ => heavy_scalar,
+    }
+}
+
+// Edits go to the player and to the tuning resource, so they survive a
+// respawn and can be saved.
+fn edit_physics(
+    actions: Res<Input<Action>>,
+    mut panel: ResMut<DebugPanel>,
+    mut tuning: ResMut<PlayerTuning>,
+    mut handle: ResMut<TuningHandle>,
+    mut query: Query<&mut Physics, With<Player>>,
+) {
+    if actions.just_pressed(Action::DebugPanel) {
+        panel.visible = !panel.visible;
+    }
+
+    if !panel.visible {
+        return;
+    }
+
+    if actions.just_pressed(Action::DebugUp) {
+        panel.selected = (panel.selected + FIELDS.len() - 1) % FIELDS.len();
+    }
+
+    if actions.just_pressed(Action::DebugDown) {
+        panel.selected = (panel.selected + 1) % FIELDS.len();
+    }
+
+    let step = FIELDS[panel.selected].1;
+    let mut delta = 0.;
+    if actions.just_pressed(Action::DebugDecrease) {
+        delta -= step;
+    }
+    if actions.just_pressed(Action::DebugIncrease) {
+        delta += step;
+    }
+
+    if delta != 0. {
+        for mut physics in query.iter_mut() {
+            let physics = &mut *physics;
+            let value = field_mut(
+                panel.selected,
+                &mut physics.velocity,
+                &mut physics.jump_distance,
+                &mut physics.jump_height,
+                &mut physics.heavy_scalar,
+            );
+            // Every field is a divisor or a scale, keep them positive
+            *value = (*value + delta).max(step);
+            let value = *value;
+            physics.derive();
+
+            let tuning = &mut *tuning;
+            *field_mut(
+                panel.selected,
+                &mut tuning.velocity,
+                &mut tuning.jump_distance,
+                &mut tuning.jump_height,
+                &mut tuning.heavy_scalar,
+            ) = value;
+        }
+    }
+
+    if actions.just_pressed(Action::DebugSave) {
+        handle.saved = save_tuning(&tuning);
+    }
+}
+
+// Write the fields the panel edits into the tuning file. Only their lines
+// change so the layout and comments of the file are kept.
+fn save_tuning(tuning: &PlayerTuning) -> bool {
+    let path = asset_path(TUNING_FILE_PATH);
+    let values = [
+        tuning.velocity,
+        tuning.jump_distance,
+        tuning.jump_height,
+        tuning.heavy_scalar,
+    ];
+
+    let result = fs::read_to_string(&path).and_then(|mut text| {
+        for ((name, _), value) in FIELDS.iter().zip(values.iter()) {
+            text = set_field(&text, name, *value);
+        }
+        fs::write(&path, text)
+    });
+
+    match result {
+        Ok(()) => {
+            println!("Saved tuning to {}", path.display());
+            true
+        }
+        Err(e) => {
+            println!("Error: {:?}", e);
+            false
+        }
+    }
+}
+
+// Replace the value on the line of a field, keeping its indentation and
+// anything after the value. A field missing from the file is added before
+// the closing parenthesis.
+fn set_field(text: &str, name: &str, value: f32) -> String {
+    let key = format!("{}:", name);
+    let mut found = false;
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            if found || !trimmed.starts_with(&key) {
+                return line.to_string();
+            }
+            found = true;
+            let indent = &line[..line.len() - trimmed.len()];
+            let old = trimmed[key.len()..].trim_start();
+            let end = old
+                .find(|c: char| c == ',' || c.is_whitespace())
+                .unwrap_or(old.len());
+            format!("{}{} {:?}{}", indent, key, value, &old[end..])
+        })
+        .collect();
+
+    if !found {
+        let close = lines.iter().rposition(|line| line.trim() == ")");
+        let end = close.unwrap_or(lines.len());
+
+        // The field before the new one needs a comma after it
+        let code = |line: &String| {
+            line.split("//").next().unwrap_or_default().trim_end().len()
+        };
+        if let Some(last) =
+            lines[..end].iter_mut().rev().find(|line| code(line) > 0)
+        {
+            let length = code(last);
+            if !last[..length].ends_with(',') && !last[..length].ends_with('(')
+            {
+                last.insert(length, ',');
+            }
+        }
+        lines.insert(end, format!("    {} {:?},", key, value));
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+fn draw_panel(
+    panel: Res<DebugPanel>,
+    deaths: Res<DeathCount>,
+    physics: Query<&Physics, With<Player>>,
+    mut text: Query<(&mut Text, &mut Visible), With<DebugText>>,
+) {
+    for (mut text, mut visible) in text.iter_mut() {
+        visible.is_visible = panel.visible;
+        if !panel.visible {
+            continue;
+        }
+
+        let physics = match physics.single() {
+            Ok(physics) => physics,
+            Err(_) => continue,
+        };
+
+        let values = [
+            physics.velocity,
+            physics.jump_distance,
+            physics.jump_height,
+            physics.heavy_scalar,
+        ];
+        let mut value = String::new();
+        for (index, ((name, _), field)) in
+            FIELDS.iter().zip(values.iter()).enumerate()
+        {
+            let cursor = if index == panel.selected { ">" } else { " " };
+            value += &format!("{} {:<14}{:>9.2}\n", cursor, name, field);
+        }
+        value += &format!(
+            "\n  {:<14}{:>9.2}\n  {:<14}{:>9.2}\n  {:<14}{:>9.2}\n",
+            "jump_velocity",
+            physics.jump_velocity,
+            "jump_gravity",
+            physics.jump_gravity,
+            "gravity",
+            physics.gravity,
+        );
+        value += &format!("\n  {:<14}{:>9}\n", "deaths", deaths.0);
+        value += "\nUp/Down select, Left/Right edit, F5 save";
+
+        text.sections[0].value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_value() {
+        let text = "(\n    velocity: 50.0,\n    jump_height: 20.0,\n)\n";
+        assert_eq!(
+            set_field(text, "velocity", 42.0),
+            "(\n    velocity: 42.0,\n    jump_height: 20.0,\n)\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let text = "// Player tuning\n(\n    velocity: 50.0, // m/s\n)\n";
+        assert_eq!(
+            set_field(text, "velocity", 42.0),
+            "// Player tuning\n(\n    velocity: 42.0, // m/s\n)\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_without_a_comma() {
+        let text = "(\n    velocity: 50.0 // m/s\n)\n";
+        assert_eq!(
+            set_field(text, "velocity", 42.0),
+            "(\n    velocity: 42.0 // m/s\n)\n"
+        );
+    }
+
+    #[test]
+    fn skips_fields_ending_in_the_name() {
+        let text =
+            "(\n    wall_slide_velocity: 20.0,\n    velocity: 50.0,\n)\n";
+        assert_eq!(
+            set_field(text, "velocity", 42.0),
+            "(\n    wall_slide_velocity: 20.0,\n    velocity: 42.0,\n)\n"
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_field() {
+        let text = "(\n    velocity: 50.0,\n)\n";
+        assert_eq!(
+            set_field(text, "heavy_scalar", 2.5),
+            "(\n    velocity: 50.0,\n    heavy_scalar: 2.5,\n)\n"
+        );
+    }
+
+    #[test]
+    fn adds_a_comma_before_a_missing_field() {
+        let text = "(\n    velocity: 50.0 // m/s\n)\n";
+        assert_eq!(
+            set_field(text, "heavy_scalar", 2.5),
+            "(\n    velocity: 50.0, // m/s\n    heavy_scalar: 2.5,\n)\n"
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_field_without_a_closing_paren() {
+        assert_eq!(
+            set_field("(\n", "velocity", 42.0),
+            "(\n    velocity: 42.0,\n"
+        );
+    }
+}
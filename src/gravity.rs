@@ -0,0 +1,204 @@
+use bevy::prelude::*;
+use bevy_rapier2d::na::Rotation2;
+use bevy_rapier2d::prelude::*;
+use std::f32::consts::TAU;
+
+// Gravity for bodies that don't set their own strength
+pub const DEFAULT_STRENGTH: f32 = -100.;
+
+// Seconds it takes gravity to turn by a quarter
+pub const DEFAULT_ROTATION_DURATION: f32 = 0.25;
+
+// Gravity along a basis of its own. Rapier's global gravity is zero, so
+// link_physics turns this into a force on any body that has it, scaled by
+// the body's mass so that everything falls at the same rate.
+pub struct Gravity {
+    // Basis has the columns [right, up]
+    pub basis: Matrix<f32>,
+    // The basis is a rotation by angle radians, which turns towards
+    // target_angle over time
+    pub angle: f32,
+    pub target_angle: f32,
+    // Seconds a quarter turn takes
+    pub rotation_duration: f32,
+
+    // Acceleration along up in meters per second squared, negative values
+    // pull down
+    pub strength: f32,
+    // Set by gravity zones, scales strength
+    pub scale: f32,
+    // No force is applied while suspended, used by the dash
+    pub suspended: bool,
+    // When set, gravity pulls straight into this floor instead of down the
+    // basis, so a body standing on a slope doesn't slide down it
+    pub floor_normal: Option<Vector<f32>>,
+}
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Gravity::new(DEFAULT_STRENGTH)
+    }
+}
+
+impl Gravity {
+    pub fn new(strength: f32) -> Self {
+        Gravity {
+            basis: Matrix::new(1., 0., 0., 1.),
+            angle: 0.,
+            target_angle: 0.,
+            rotation_duration: DEFAULT_ROTATION_DURATION,
+            strength,
+            scale: 1.,
+            suspended: false,
+            floor_normal: None,
+        }
+    }
+
+    // Start turning by radians, see turn_gravity
+    pub fn rotate(&mut self, radians: f32) {
+        self.target_angle += radians;
+    }
+
+    // Start turning to radians, taking the short way round
+    pub fn turn_to(&mut self, radians: f32) {
+        let difference = (radians - self.target_angle).rem_euclid(TAU);
+        if difference > TAU / 2. {
+            self.target_angle += difference - TAU;
+        } else {
+            self.target_angle += difference;
+        }
+    }
+
+    // Turn to radians right away
+    pub fn set_angle(&mut self, radians: f32) {
+        self.angle = radians;
+        self.target_angle = radians;
+        self.basis = Rotation2::new(radians).into_inner();
+    }
+
+    // Move angle towards target_angle at a quarter turn per
+    // rotation_duration
+    pub fn step_rotation(&mut self, delta: f32) {
+        let remaining = self.target_angle - self.angle;
+        let step = if self.rotation_duration > 0. {
+            (TAU / 4.) * delta / self.rotation_duration
+        } else {
+            remaining.abs()
+        };
+        self.angle += remaining.clamp(-step, step);
+        self.basis = Rotation2::new(self.angle).into_inner();
+    }
+
+    pub fn get_gravity(&self) -> Vector<f32> {
+        if let Some(normal) = self.floor_normal {
+            return normal * self.strength * self.scale;
+        }
+        let scale: Vector<f32> = [0., self.strength * self.scale].into();
+        self.basis * scale
+    }
+}
+
+// The world's gravity direction. Levels can enable it so every body with
+// Gravity turns together with the world instead of on its own, bodies
+// still keep their own strength.
+#[derive(Default)]
+pub struct GlobalGravity {
+    pub enabled: bool,
+    pub gravity: Gravity,
+}
+
+impl GlobalGravity {
+    // The gravity that turning a body should turn, the world's when it is
+    // enabled and the body's own otherwise
+    pub fn target<'a>(&'a mut self, own: &'a mut Gravity) -> &'a mut Gravity {
+        if self.enabled {
+            &mut self.gravity
+        } else {
+            own
+        }
+    }
+}
+
+pub fn link_physics(
+    mut query: Query<(&mut RigidBodyForces, &RigidBodyMassProps, &Gravity)>,
+) {
+    for (mut forces, mass_props, gravity) in query.iter_mut() {
+        if gravity.suspended {
+            forces.force = [0., 0.].into();
+        } else {
+            forces.force = gravity.get_gravity() * mass_props.mass();
+        }
+    }
+}
+
+// Animate gravity changes, bodies copy the world's rotation while global
+// gravity is enabled
+pub fn turn_gravity(
+    time: Res<Time>,
+    mut global: ResMut<GlobalGravity>,
+    mut query: Query<&mut Gravity>,
+) {
+    global.gravity.step_rotation(time.delta_seconds());
+
+    for mut gravity in query.iter_mut() {
+        if global.enabled {
+            gravity.basis = global.gravity.basis;
+            gravity.angle = global.gravity.angle;
+            gravity.target_angle = global.gravity.target_angle;
+        } else {
+            gravity.step_rotation(time.delta_seconds());
+        }
+    }
+}
+
+// A region that turns the gravity of any body with Gravity that enters it,
+// or the whole world's when global gravity is enabled.
+// Leaving the zone keeps the new direction but drops the strength back to
+// normal.
+pub struct GravityZone {
+    // Angle of the basis inside the zone, see Gravity::turn_to
+    pub rotation: f32,
+    // Scale applied to gravity while inside the zone
+    pub strength: f32,
+}
+
+pub fn enter_gravity_zone(
+    mut events: EventReader<IntersectionEvent>,
+    narrow_phase: Res<NarrowPhase>,
+    mut global: ResMut<GlobalGravity>,
+    zones: Query<&GravityZone>,
+    mut bodies: Query<&mut Gravity>,
+) {
+    for event in events.iter() {
+        let a = event.collider1.entity();
+        let b = event.collider2.entity();
+
+        for (zone, body) in [(a, b), (b, a)].iter() {
+            let zone = match zones.get(*zone) {
+                Ok(zone) => zone,
+                Err(_) => continue,
+            };
+            let mut gravity = match bodies.get_mut(*body) {
+                Ok(gravity) => gravity,
+                Err(_) => continue,
+            };
+
+            if event.intersecting {
+                global.target(&mut gravity).turn_to(zone.rotation);
+                gravity.scale = zone.strength;
+            } else {
+                // Zones can overlap, keep the strength of one the body is
+                // still inside
+                let handle = body.handle();
+                gravity.scale = narrow_phase
+                    .intersections_with(handle)
+                    .filter(|(_, _, intersecting)| *intersecting)
+                    .map(|(c1, c2, _)| if c1 == handle { c2 } else { c1 })
+                    .filter_map(|other| zones.get(other.entity()).ok())
+                    .map(|zone| zone.strength)
+                    .last()
+                    .unwrap_or(1.);
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use bevy::app::AppExit;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::debug::FONT_PATH;
+use crate::ldtk::asset_path;
+
+pub const BINDINGS_FILE_PATH: &str = "input.bindings.ron";
+
+const FONT_SIZE: f32 = 16.;
+
+// What the player can do. Gameplay systems read these from Input<Action>
+// instead of reading keys.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Jump,
+    Dash,
+    RotateCW,
+    RotateCCW,
+    Restart,
+    Pause,
+    Quit,
+    Level1,
+    Level2,
+    Level3,
+    Level4,
+    Level5,
+    Level6,
+    Level7,
+    Level8,
+    Level9,
+    DebugPanel,
+    DebugUp,
+    DebugDown,
+    DebugDecrease,
+    DebugIncrease,
+    DebugSave,
+    AlignCamera,
+    CameraShake,
+}
+
+// The actions that jump to a level, in the order of the levels
+pub const LEVEL_ACTIONS: &[Action] = &[
+    Action::Level1,
+    Action::Level2,
+    Action::Level3,
+    Action::Level4,
+    Action::Level5,
+    Action::Level6,
+    Action::Level7,
+    Action::Level8,
+    Action::Level9,
+];
+
+const ACTIONS: &[Action] = &[
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::MoveUp,
+    Action::MoveDown,
+    Action::Jump,
+    Action::Dash,
+    Action::RotateCW,
+    Action::RotateCCW,
+    Action::Restart,
+    Action::Pause,
+    Action::Quit,
+    Action::Level1,
+    Action::Level2,
+    Action::Level3,
+    Action::Level4,
+    Action::Level5,
+    Action::Level6,
+    Action::Level7,
+    Action::Level8,
+    Action::Level9,
+    Action::DebugPanel,
+    Action::DebugUp,
+    Action::DebugDown,
+    Action::DebugDecrease,
+    Action::DebugIncrease,
+    Action::DebugSave,
+    Action::AlignCamera,
+    Action::CameraShake,
+];
+
+// The keys bound to each action, any of them triggers it.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Bindings(pub BTreeMap<Action, Vec<KeyCode>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = BTreeMap::new();
+        for action in ACTIONS.iter() {
+            bindings.insert(*action, vec![default_key(*action)]);
+        }
+        Bindings(bindings)
+    }
+}
+
+fn default_key(action: Action) -> KeyCode {
+    match action {
+        Action::MoveLeft => KeyCode::A,
+        Action::MoveRight => KeyCode::D,
+        Action::MoveUp => KeyCode::W,
+        Action::MoveDown => KeyCode::S,
+        Action::Jump => KeyCode::Space,
+        Action::Dash => KeyCode::LShift,
+        Action::RotateCW => KeyCode::Q,
+        Action::RotateCCW => KeyCode::E,
+        Action::Restart => KeyCode::R,
+        Action::Pause => KeyCode::P,
+        Action::Quit => KeyCode::Escape,
+        Action::Level1 => KeyCode::Key1,
+        Action::Level2 => KeyCode::Key2,
+        Action::Level3 => KeyCode::Key3,
+        Action::Level4 => KeyCode::Key4,
+        Action::Level5 => KeyCode::Key5,
+        Action::Level6 => KeyCode::Key6,
+        Action::Level7 => KeyCode::Key7,
+        Action::Level8 => KeyCode::Key8,
+        Action::Level9 => KeyCode::Key9,
+        Action::DebugPanel => KeyCode::F1,
+        Action::DebugUp => KeyCode::Up,
+        Action::DebugDown => KeyCode::Down,
+        Action::DebugDecrease => KeyCode::Left,
+        Action::DebugIncrease => KeyCode::Right,
+        Action::DebugSave => KeyCode::F5,
+        Action::AlignCamera => KeyCode::F2,
+        Action::CameraShake => KeyCode::F3,
+    }
+}
+
+impl Bindings {
+    // Bind key to action alone. Another action that had the key takes over
+    // the action's old keys instead, so no key triggers two actions.
+    fn bind(&mut self, action: Action, key: KeyCode) {
+        let old = self.0.insert(action, vec![key]).unwrap_or_default();
+        let mut old = Some(old);
+        for (other, keys) in self.0.iter_mut() {
+            if *other == action || !keys.contains(&key) {
+                continue;
+            }
+            keys.retain(|k| *k != key);
+            if keys.is_empty() {
+                let old = old.take().unwrap_or_default();
+                *keys = old.into_iter().filter(|k| *k != key).collect();
+            }
+        }
+    }
+
+    // Give actions missing from the file their default key, unless another
+    // action already uses it.
+    fn fill_defaults(&mut self) {
+        for action in ACTIONS.iter() {
+            if self.0.contains_key(action) {
+                continue;
+            }
+            let key = default_key(*action);
+            let taken = self.0.values().any(|keys| keys.contains(&key));
+            let keys = if taken { Vec::new() } else { vec![key] };
+            self.0.insert(*action, keys);
+        }
+    }
+}
+
+// Pause opens the rebinding screen and stops the game. Up/Down select an
+// action, Enter waits for the next key to bind to it, or Escape to cancel,
+// and Back restores its default key.
+#[derive(Default)]
+pub struct RebindScreen {
+    pub open: bool,
+    selected: usize,
+    waiting: bool,
+}
+
+pub struct RebindText;
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(load_bindings())
+            .insert_resource(Input::<Action>::default())
+            .insert_resource(RebindScreen::default())
+            .add_startup_system(setup.system())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_actions.system().after(InputSystem),
+            )
+            .add_system(rebind.system())
+            .add_system(quit.system())
+            .add_system(draw_screen.system());
+    }
+}
+
+// Missing or broken binding files fall back to the default keys, and
+// actions missing from the file get theirs.
+fn load_bindings() -> Bindings {
+    let path = asset_path(BINDINGS_FILE_PATH);
+    let result = fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| {
+            ron::from_str::<Bindings>(&text).map_err(anyhow::Error::from)
+        });
+
+    match result {
+        Ok(mut bindings) => {
+            bindings.fill_defaults();
+            bindings
+        }
+        Err(e) => {
+            println!("Error: {:?}", e);
+            Bindings::default()
+        }
+    }
+}
+
+fn save_bindings(bindings: &Bindings) {
+    let path = asset_path(BINDINGS_FILE_PATH);
+    let result = ron::ser::to_string_pretty(bindings, PrettyConfig::default())
+        .map_err(anyhow::Error::from)
+        .and_then(|text| fs::write(&path, text).map_err(anyhow::Error::from));
+
+    match result {
+        Ok(()) => println!("Saved bindings to {}", path.display()),
+        Err(e) => println!("Error: {:?}", e),
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Percent(5.),
+                    left: Val::Percent(35.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: FONT_SIZE,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(RebindText);
+}
+
+// Run criterion for gameplay systems, which stop while the rebinding
+// screen is open
+pub fn playing(screen: Res<RebindScreen>) -> ShouldRun {
+    if screen.open {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+// Turn key presses into actions. An action starts when one of its keys is
+// pressed and stops once none are held. Only Pause and Quit work while the
+// rebinding screen is open, which keeps the debug panel from following its
+// keys too, and nothing works while it waits for a key.
+fn update_actions(
+    kb: Res<Input<KeyCode>>,
+    bindings: Res<Bindings>,
+    screen: Res<RebindScreen>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    actions.update();
+
+    for (action, keys) in bindings.0.iter() {
+        let active = !screen.waiting
+            && (!screen.open || matches!(action, Action::Pause | Action::Quit));
+        let held = keys.iter().any(|key| kb.pressed(*key));
+        let hit = keys.iter().any(|key| kb.just_pressed(*key));
+
+        if active && hit && !actions.pressed(*action) {
+            actions.press(*action);
+        } else if (!active || !held) && actions.pressed(*action) {
+            actions.release(*action);
+        }
+    }
+}
+
+fn rebind(
+    kb: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
+    mut screen: ResMut<RebindScreen>,
+    mut bindings: ResMut<Bindings>,
+    mut rapier: ResMut<RapierConfiguration>,
+) {
+    if screen.waiting {
+        if kb.just_pressed(KeyCode::Escape) {
+            screen.waiting = false;
+        } else if let Some(key) = kb.get_just_pressed().next() {
+            bindings.bind(ACTIONS[screen.selected], *key);
+            screen.waiting = false;
+            save_bindings(&bindings);
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::Pause) {
+        screen.open = !screen.open;
+        rapier.physics_pipeline_active = !screen.open;
+    }
+
+    if !screen.open {
+        return;
+    }
+
+    if kb.just_pressed(KeyCode::Up) {
+        screen.selected = (screen.selected + ACTIONS.len() - 1) % ACTIONS.len();
+    }
+
+    if kb.just_pressed(KeyCode::Down) {
+        screen.selected = (screen.selected + 1) % ACTIONS.len();
+    }
+
+    if kb.just_pressed(KeyCode::Return) {
+        screen.waiting = true;
+    }
+
+    if kb.just_pressed(KeyCode::Back) {
+        let action = ACTIONS[screen.selected];
+        bindings.bind(action, default_key(action));
+        save_bindings(&bindings);
+    }
+}
+
+fn quit(actions: Res<Input<Action>>, mut exit: EventWriter<AppExit>) {
+    if actions.just_pressed(Action::Quit) {
+        exit.send(AppExit);
+    }
+}
+
+fn draw_screen(
+    screen: Res<RebindScreen>,
+    bindings: Res<Bindings>,
+    mut text: Query<(&mut Text, &mut Visible), With<RebindText>>,
+) {
+    for (mut text, mut visible) in text.iter_mut() {
+        visible.is_visible = screen.open;
+        if !screen.open {
+            continue;
+        }
+
+        let mut value = String::from("Paused\n\n");
+        for (index, action) in ACTIONS.iter().enumerate() {
+            let cursor = if index == screen.selected { ">" } else { " " };
+            let keys = if screen.waiting && index == screen.selected {
+                String::from("press a key")
+            } else {
+                bindings
+                    .0
+                    .get(action)
+                    .map(|keys| {
+                        keys.iter()
+                            .map(|key| format!("{:?}", key))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })
+                    .unwrap_or_default()
+            };
+            value += &format!(
+                "{} {:<13} {}\n",
+                cursor,
+                format!("{:?}", action),
+                keys
+            );
+        }
+        value += "\nUp/Down select, Enter rebind, Back default\n";
+        value += "Escape cancels a rebind";
+
+        text.sections[0].value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bindings: &Bindings, action: Action) -> Vec<KeyCode> {
+        bindings.0[&action].clone()
+    }
+
+    #[test]
+    fn binds_a_free_key() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Jump, KeyCode::J);
+        assert_eq!(keys(&bindings, Action::Jump), vec![KeyCode::J]);
+        assert_eq!(keys(&bindings, Action::Dash), vec![KeyCode::LShift]);
+    }
+
+    #[test]
+    fn swaps_a_taken_key() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Jump, KeyCode::A);
+        assert_eq!(keys(&bindings, Action::Jump), vec![KeyCode::A]);
+        assert_eq!(keys(&bindings, Action::MoveLeft), vec![KeyCode::Space]);
+    }
+
+    #[test]
+    fn keeps_the_other_keys_of_an_action() {
+        let mut bindings = Bindings::default();
+        bindings
+            .0
+            .insert(Action::MoveLeft, vec![KeyCode::A, KeyCode::Left]);
+        bindings.bind(Action::Jump, KeyCode::A);
+        assert_eq!(keys(&bindings, Action::MoveLeft), vec![KeyCode::Left]);
+        assert!(bindings
+            .0
+            .values()
+            .all(|keys| !keys.contains(&KeyCode::Space)));
+    }
+
+    #[test]
+    fn rebinding_the_same_key_changes_nothing() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Jump, KeyCode::Space);
+        assert_eq!(keys(&bindings, Action::Jump), vec![KeyCode::Space]);
+        assert_eq!(bindings.0.len(), ACTIONS.len());
+    }
+
+    #[test]
+    fn only_one_action_takes_the_old_keys() {
+        let mut bindings = Bindings::default();
+        bindings.0.insert(Action::MoveLeft, vec![KeyCode::J]);
+        bindings.0.insert(Action::MoveRight, vec![KeyCode::J]);
+        bindings.bind(Action::Jump, KeyCode::J);
+        let space = bindings
+            .0
+            .values()
+            .filter(|keys| keys.contains(&KeyCode::Space))
+            .count();
+        assert_eq!(space, 1);
+    }
+
+    #[test]
+    fn fills_missing_actions_with_free_defaults() {
+        let mut bindings = Bindings::default();
+        bindings.0.remove(&Action::Quit);
+        bindings.0.remove(&Action::Pause);
+        bindings.0.insert(Action::Restart, vec![KeyCode::P]);
+        bindings.fill_defaults();
+        assert_eq!(keys(&bindings, Action::Quit), vec![KeyCode::Escape]);
+        assert!(keys(&bindings, Action::Pause).is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use bevy_rapier2d::physics::PhysicsSystems;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::data::ComponentSet;
+
+use crate::contact::{is_solid, Contacts};
+use crate::death::Dying;
+use crate::gravity::Gravity;
+use crate::input::playing;
+use crate::player::{Physics, PlayerSystem};
+
+// Gap kept between a kinematic body and whatever it touches, in meters
+const SKIN: f32 = 0.02;
+
+// How many surfaces one move can slide along before it stops
+const MAX_SLIDES: usize = 4;
+
+// Marks a body moved by move_and_slide instead of Rapier's solver. Its
+// RigidBodyVelocity is still what the controller systems write, Rapier
+// sets it back to the distance really moved after each step.
+pub struct Kinematic;
+
+pub struct KinematicPlugin;
+
+impl Plugin for KinematicPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // Rapier steps the world in the same stage and overwrites the
+        // velocity of kinematic bodies, so this runs after every system
+        // that sets the player's velocity and before the step
+        app.add_system(
+            move_and_slide
+                .system()
+                .with_run_criteria(playing.system())
+                .after(PlayerSystem::Velocity)
+                .before(PhysicsSystems::StepWorld),
+        );
+    }
+}
+
+// Shape casts against the world for one body
+struct Caster<'a, Colliders> {
+    pipeline: &'a QueryPipeline,
+    colliders: &'a Colliders,
+    shape: &'a dyn Shape,
+    filter: &'a dyn Fn(ColliderHandle) -> bool,
+}
+
+impl<'a, Colliders> Caster<'a, Colliders>
+where
+    Colliders: ComponentSet<ColliderFlags>
+        + ComponentSet<ColliderPosition>
+        + ComponentSet<ColliderShape>,
+{
+    // How far the shape gets along motion, and the normal of whatever
+    // stopped it. Anything within SKIN past the end counts as a hit.
+    fn cast(
+        &self,
+        position: &Isometry<f32>,
+        motion: Vector<f32>,
+    ) -> (Vector<f32>, Option<Vector<f32>>) {
+        let distance = motion.norm();
+        if distance <= f32::EPSILON {
+            return (motion, None);
+        }
+
+        let direction = motion / distance;
+        match self.pipeline.cast_shape(
+            self.colliders,
+            position,
+            &direction,
+            self.shape,
+            distance + SKIN,
+            InteractionGroups::all(),
+            Some(self.filter),
+        ) {
+            Some((_, toi)) => {
+                (direction * (toi.toi - SKIN).max(0.), Some(*toi.normal1))
+            }
+            None => (motion, None),
+        }
+    }
+
+    // Move position by motion, sliding along what it hits. The normals of
+    // the hits are added to normals.
+    fn slide(
+        &self,
+        position: &mut Isometry<f32>,
+        motion: Vector<f32>,
+        normals: &mut Vec<Vector<f32>>,
+    ) {
+        let mut remaining = motion;
+        for _ in 0..MAX_SLIDES {
+            let (moved, normal) = self.cast(position, remaining);
+            position.translation.vector += moved;
+
+            let normal = match normal {
+                Some(normal) => normal,
+                None => break,
+            };
+            normals.push(normal);
+            remaining -= moved;
+            remaining -= normal * remaining.dot(&normal);
+        }
+    }
+}
+
+type KinematicQuery<'a> = (
+    Entity,
+    &'a Physics,
+    &'a Gravity,
+    &'a ColliderShape,
+    &'a mut Contacts,
+    &'a mut RigidBodyVelocity,
+    &'a mut RigidBodyPosition,
+);
+
+// Move kinematic bodies by their velocity: across first, stepping up onto
+// anything lower than step_offset, then along up. Bodies that were on the
+// floor are kept on it when it drops away by less than snap_distance.
+pub fn move_and_slide(
+    integration_parameters: Res<IntegrationParameters>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    collider_types: Query<&ColliderType>,
+    mut query: Query<KinematicQuery, (With<Kinematic>, Without<Dying>)>,
+) {
+    // Rapier turns the move back into a velocity with its own time step
+    let dt = integration_parameters.dt;
+    let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (
+        entity,
+        physics,
+        gravity,
+        shape,
+        mut contacts,
+        mut velocity,
+        mut position,
+    ) in query.iter_mut()
+    {
+        let handle = entity.handle();
+        let solid =
+            |other: ColliderHandle| is_solid(handle, other, &collider_types);
+        let caster = Caster {
+            pipeline: &query_pipeline,
+            colliders: &colliders,
+            shape: &**shape,
+            filter: &solid,
+        };
+
+        if !gravity.suspended {
+            velocity.linvel += gravity.get_gravity() * dt;
+        }
+
+        let right = gravity.basis * Vector::new(1., 0.);
+        let up = gravity.basis * Vector::new(0., 1.);
+        let local = gravity.basis.transpose() * velocity.linvel * dt;
+        let was_grounded = contacts.grounded();
+
+        let start = position.position;
+        let mut next = start;
+        let mut normals = Vec::new();
+        caster.slide(&mut next, right * local.x, &mut normals);
+
+        if was_grounded && !normals.is_empty() && physics.step_offset > 0. {
+            let mut stepped = start;
+            let mut stepped_normals = Vec::new();
+            let (rise, _) = caster.cast(&stepped, up * physics.step_offset);
+            stepped.translation.vector += rise;
+            caster.slide(&mut stepped, right * local.x, &mut stepped_normals);
+            let (fall, _) = caster.cast(&stepped, -rise);
+            stepped.translation.vector += fall;
+
+            let progress = |position: &Isometry<f32>| {
+                (position.translation.vector - start.translation.vector)
+                    .dot(&right)
+                    .abs()
+            };
+            if progress(&stepped) > progress(&next) + SKIN {
+                next = stepped;
+                normals = stepped_normals;
+            }
+        }
+
+        caster.slide(&mut next, up * local.y, &mut normals);
+
+        if was_grounded && local.y <= 0. {
+            let (drop, normal) =
+                caster.cast(&next, -up * physics.snap_distance);
+            if let Some(normal) = normal {
+                next.translation.vector += drop;
+                normals.push(normal);
+            }
+        }
+
+        let mut found = Contacts {
+            max_slope: contacts.max_slope,
+            ..Default::default()
+        };
+        for normal in normals {
+            found.add_normal(&gravity.basis, normal);
+        }
+        *contacts = found;
+
+        position.next_position = next;
+    }
+}
@@ -11,24 +11,221 @@
 // Also, I'm not a Bevy expert so I'd welcome corrections
 // and/or better examples.
 
+use bevy::asset::{
+    AssetLoader, FileAssetIo, LoadContext, LoadState, LoadedAsset,
+};
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
 use bevy::render::pass::ClearColor;
-use ldtk_rust::{EntityInstance, Project, TileInstance};
+use bevy::utils::BoxedFuture;
+use bevy_rapier2d::prelude::*;
+use ldtk_rust::{EntityInstance, Level, Project, TileInstance};
 
 use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::path::{Path, PathBuf};
+
+use crate::camera::CameraRoom;
+use crate::checkpoint::{Checkpoint, RespawnPoint};
+use crate::death::Hazard;
+use crate::gravity::{GlobalGravity, Gravity, GravityZone};
+use crate::input::{playing, Action, LEVEL_ACTIONS};
+use crate::player::{MoveToStart, Player, PlayerStart};
+use crate::METERS_TO_PIXELS;
 
 // Constants
-const LDTK_FILE_PATH: &str = "assets/test_map.ldtk";
-const TILE_SCALE: f32 = 5.;
+const ASSET_FOLDER: &str = "assets";
+const LDTK_FILE_PATH: &str = "test_map.ldtk";
+
+// Where the AssetServer reads an asset from, for files the game writes
+// back. The asset folder is found next to the manifest when run by cargo
+// and next to the executable otherwise, not in the working directory.
+pub fn asset_path(path: &str) -> PathBuf {
+    FileAssetIo::get_root_path().join(ASSET_FOLDER).join(path)
+}
+
+// How many LDtk pixels make up one meter of game world. Tiles are scaled so
+// that the level lines up with the physics world, which keeps sprites and
+// colliders in the same unit system as the player.
+const LDTK_PIXELS_PER_METER: f32 = 4.;
+pub const TILE_SCALE: f32 = METERS_TO_PIXELS / LDTK_PIXELS_PER_METER;
+
+// IntGrid values with one of these identifiers get static colliders.
+const SOLID_INT_GRID_VALUES: &[&str] = &["walls"];
+
+// IntGrid values with one of these identifiers kill the player on touch.
+const HAZARD_INT_GRID_VALUES: &[&str] = &["spikes", "lava"];
 
 // Extend the LdtkFile object with whatever you need for your
 // game engine. In a real game you might need a variety of
 // fields to control how and when you use the LDtk information.
 
-struct Map {
-    ldtk_file: Project,
+pub struct Map {
+    handle: Handle<LdtkProject>,
     redraw: bool,
+    // Whether a level has been drawn since the game started
+    drawn: bool,
     current_level: usize,
+    // Whether the next redraw should send the player to the level's start
+    move_to_start: bool,
+}
+
+impl Map {
+    pub fn current_level(&self) -> usize {
+        self.current_level
+    }
+
+    // Whether the file failed to load before any level could be drawn. A
+    // reload that fails keeps the level that is already there.
+    pub fn load_failed(&self, asset_server: &AssetServer) -> bool {
+        !self.drawn
+            && asset_server.get_load_state(&self.handle) == LoadState::Failed
+    }
+
+    // None until load_project catches up with a reload that removed the
+    // current level
+    fn level<'a>(&self, project: &'a Project) -> Option<&'a Level> {
+        project.levels.get(self.current_level)
+    }
+}
+
+fn level_index(project: &Project, target: &LevelTarget) -> Option<usize> {
+    match target {
+        LevelTarget::Index(index) => {
+            Some(*index).filter(|index| *index < project.levels.len())
+        }
+        LevelTarget::Identifier(identifier) => project
+            .levels
+            .iter()
+            .position(|level| &level.identifier == identifier),
+        LevelTarget::Uid(uid) => {
+            project.levels.iter().position(|level| level.uid == *uid)
+        }
+    }
+}
+
+// The LDtk project as a Bevy asset, so it can be loaded (and reloaded when
+// the file changes) by the AssetServer.
+#[derive(TypeUuid)]
+#[uuid = "620aa42f-f6ce-4c6e-bbcf-b9f8aa6127dc"]
+pub struct LdtkProject(pub Project);
+
+#[derive(Default)]
+pub struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut project: Project = serde_json::from_slice(bytes)?;
+
+            // Projects saved with "separate level files" only keep a stub of
+            // each level, so read the real levels from their own files.
+            if project.external_levels {
+                let parent = load_context
+                    .path()
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                for level in project.levels.iter_mut() {
+                    if let Some(rel_path) = &level.external_rel_path {
+                        let bytes = load_context
+                            .read_asset_bytes(parent.join(rel_path))
+                            .await?;
+                        *level = serde_json::from_slice(&bytes)?;
+                    }
+                }
+            }
+
+            load_context
+                .set_default_asset(LoadedAsset::new(LdtkProject(project)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
+
+// Half of a level's size in meters. Levels are drawn centered on the
+// origin, so this is also where the level's edges are.
+fn level_half_extents(level: &Level) -> Vector<f32> {
+    [
+        level.px_wid as f32 / LDTK_PIXELS_PER_METER / 2.,
+        level.px_hei as f32 / LDTK_PIXELS_PER_METER / 2.,
+    ]
+    .into()
+}
+
+// Convert a position in a level (meters, centered on the origin) into LDtk
+// world pixels, which are shared by every level.
+fn level_to_world(level: &Level, position: Vector<f32>) -> Vector<f32> {
+    let half = level_half_extents(level);
+    [
+        level.world_x as f32 + (position.x + half.x) * LDTK_PIXELS_PER_METER,
+        level.world_y as f32 + (half.y - position.y) * LDTK_PIXELS_PER_METER,
+    ]
+    .into()
+}
+
+// The inverse of level_to_world.
+fn world_to_level(level: &Level, world: Vector<f32>) -> Vector<f32> {
+    let half = level_half_extents(level);
+    [
+        (world.x - level.world_x as f32) / LDTK_PIXELS_PER_METER - half.x,
+        half.y - (world.y - level.world_y as f32) / LDTK_PIXELS_PER_METER,
+    ]
+    .into()
+}
+
+// The drawn level's half size in pixels. Levels are centered on the origin,
+// so the camera keeps inside these.
+pub struct LevelBounds {
+    pub half_extents: Vec2,
+}
+
+// Marks every entity that belongs to the drawn level so it can be
+// despawned when the level changes.
+pub struct LevelEntity;
+
+// A level exit. Touching it sends the player to the start of another level.
+pub struct Exit {
+    pub level: String,
+}
+
+pub enum LevelTarget {
+    Index(usize),
+    Identifier(String),
+    Uid(i64),
+}
+
+pub enum LevelEntry {
+    // Place the player at the level's PlayerStart
+    Start,
+    // Keep the player where they are in the world, so walking off the edge
+    // of one level continues into its neighbour
+    Edge,
+    // Leave the player where they are, for when they were already placed
+    // in the new level
+    Stay,
+}
+
+// Send this event to load another level.
+pub struct ChangeLevel {
+    pub level: LevelTarget,
+    pub entry: LevelEntry,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum MapSystem {
+    Load,
+    ChangeLevel,
+    Draw,
 }
 
 // We need a place to store the assets that LDtk references
@@ -40,9 +237,9 @@ struct Map {
 // If you only use one spritesheet you could just store it
 // directly instead of using a Hash and doing all the
 // looping below.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 struct VisualAssets {
-    int_grid_materials: HashMap<i32, Vec<Handle<ColorMaterial>>>,
+    int_grid_materials: HashMap<i32, HashMap<i64, Handle<ColorMaterial>>>,
     spritesheets: HashMap<i32, Handle<TextureAtlas>>,
     entity_materials: HashMap<i32, Handle<ColorMaterial>>,
 }
@@ -51,7 +248,7 @@ struct VisualAssets {
 #[derive(Clone, Copy)]
 struct LayerInfo {
     grid_width: i32,
-    _grid_height: i32,
+    grid_height: i32,
     grid_cell_size: i32,
     z_index: i32,
     px_width: f32,
@@ -81,58 +278,146 @@ impl ExtraEntDefs {
     }
 }
 
-// Bevy specific app setup. This just means we are opening a
-// window for our game, running the setup() function once at
-// startup and then running update() every game loop.
+// A rectangle of IntGrid cells, in grid coordinates from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CellRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+// Bevy specific app setup. The LDtk file starts loading in the PreStartup
+// stage so that startup systems (like the fallback ground) can tell whether
+// a level exists. Once the AssetServer has it, load_project() prepares its
+// assets and update() draws the level every game loop.
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(setup.system())
-            .add_system(update.system());
+        app.add_asset::<LdtkProject>()
+            .init_asset_loader::<LdtkLoader>()
+            .add_event::<ChangeLevel>()
+            .add_startup_system_to_stage(
+                StartupStage::PreStartup,
+                setup.system(),
+            )
+            .add_system(
+                load_project
+                    .system()
+                    .label(MapSystem::Load)
+                    .before(MapSystem::ChangeLevel),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(playing.system())
+                    .with_system(
+                        cross_level_edge
+                            .system()
+                            .after(MapSystem::Load)
+                            .before(MapSystem::ChangeLevel),
+                    )
+                    .with_system(
+                        enter_exit.system().before(MapSystem::ChangeLevel),
+                    )
+                    .with_system(
+                        select_level.system().before(MapSystem::ChangeLevel),
+                    ),
+            )
+            .add_system(
+                change_level
+                    .system()
+                    .label(MapSystem::ChangeLevel)
+                    .before(MapSystem::Draw),
+            )
+            .add_system(update.system().label(MapSystem::Draw));
     }
 }
 
-// Our setup system will run once and will read in the LDtk file.
-// It then loops through any referenced tilesets and sets those
-// up as Bevy Assets. It does the same thing for color materials.
-// Finally it saves the LdtkFile instance and handles to all the
-// assets as Bevy Resources, which makes them "globals".
-fn setup(
+// Our setup system will run once and will start loading the LDtk file.
+// The AssetServer watches the file, so saving the project in LDtk reloads
+// it while the game is running. A missing file fails to load like a broken
+// one, and the game falls back to map::spawn_ground.
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Map {
+        handle: asset_server.load(LDTK_FILE_PATH),
+        redraw: false,
+        drawn: false,
+        current_level: 1,
+        move_to_start: true,
+    });
+    commands.insert_resource(VisualAssets::default());
+}
+
+// Runs whenever the LDtk project finishes loading. It loops through any
+// referenced tilesets and sets those up as Bevy Assets. It does the same
+// thing for color materials. Finally it saves handles to all the assets
+// in the VisualAssets resource and queues the level to be drawn.
+//
+// When the project is reloaded the current level is despawned and drawn
+// again in place, leaving the player where it is.
+#[allow(clippy::too_many_arguments)]
+fn load_project(
     mut commands: Commands,
+    mut events: EventReader<AssetEvent<LdtkProject>>,
+    mut map: ResMut<Map>,
+    mut visual_assets: ResMut<VisualAssets>,
+    projects: Res<Assets<LdtkProject>>,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    level_entities: Query<Entity, With<LevelEntity>>,
 ) {
-    // Create a new Map instance and set the values. This is where we
-    // actually load in the LDtk file.
-    let map = Map {
-        ldtk_file: Project::new(LDTK_FILE_PATH.to_string()),
-        redraw: true,
-        current_level: 1,
-    };
+    let mut reloaded = false;
+    let mut loaded = false;
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } if *handle == map.handle => {
+                loaded = true
+            }
+            AssetEvent::Modified { handle } if *handle == map.handle => {
+                reloaded = true
+            }
+            _ => (),
+        }
+    }
+    if !loaded && !reloaded {
+        return;
+    }
 
-    // Create a new VisualAssets instance. This is where we will save
-    // handles to all our assets so we can call them in our update()
-    // function later.
-    let mut visual_assets = VisualAssets {
-        int_grid_materials: HashMap::new(),
-        spritesheets: HashMap::new(),
-        entity_materials: HashMap::new(),
+    let project = match projects.get(&map.handle) {
+        Some(project) => &project.0,
+        None => return,
     };
 
+    if reloaded {
+        println!("Reloading {}", LDTK_FILE_PATH);
+        for entity in level_entities.iter() {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    // The level we were on may have been removed
+    if map.current_level >= project.levels.len() {
+        map.current_level = 0;
+        map.move_to_start = true;
+    }
+
+    *visual_assets = VisualAssets::default();
+
     // For each tileset referenced in the LDtk file, create a Texture Atlas
     // and store a Handle in a Hash. The key to the Hash is the value LDtk
     // assigns as the tileset's UID. If you know you only have one tileset
     // asset, you could simplify this and just load it like any other asset
-    // using map.ldtk_file.defs.tilesets[0].rel_path
+    // using project.defs.tilesets[0].rel_path
     //
     // Note that LDTK seems to be okay processing image files with pixel
     // dimensions that don't divide evenly by the tile size, but Bevy isn't.
     // Best to make sure your width/height are divisible evenly by your tile
     // size.
-    for tileset in map.ldtk_file.defs.tilesets.iter() {
-        let texture_handle = asset_server.load(&tileset.rel_path[..]);
+    for tileset in project.defs.tilesets.iter() {
+        let texture_handle =
+            asset_server.load(tileset_asset_path(&tileset.rel_path).as_str());
 
         let texture_atlas = TextureAtlas::from_grid(
             texture_handle,
@@ -154,17 +439,11 @@ fn setup(
     // materials for each integer value. The Bevy snake tutorial has some good sample
     // code for using materials: https://mbuffett.com/posts/bevy-snake-tutorial/
 
-    for layer in
-        map.ldtk_file
-            .defs
-            .layers
-            .iter()
-            .filter(|f| match f.purple_type {
-                ldtk_rust::Type::IntGrid => true,
-                _ => false,
-            })
-    {
-        let mut colors = Vec::new();
+    for layer in project.defs.layers.iter().filter(|f| match f.purple_type {
+        ldtk_rust::Type::IntGrid => true,
+        _ => false,
+    }) {
+        let mut colors = HashMap::new();
         for i in layer.int_grid_values.iter() {
             let clr = match Color::hex(&i.color[1..]) {
                 Ok(t) => t,
@@ -174,7 +453,7 @@ fn setup(
                 }
             };
             let col_mat = materials.add(ColorMaterial::from(clr));
-            colors.push(col_mat);
+            colors.insert(i.value, col_mat);
         }
         visual_assets
             .int_grid_materials
@@ -184,7 +463,7 @@ fn setup(
     // LDtk supports placement of Entities in levels (player, chest, health potion, etc.)
     // If you are using this feature you may want to do additional setup here beyond
     // loading in the tilemap assets above.
-    for ent in map.ldtk_file.defs.entities.iter() {
+    for ent in project.defs.entities.iter() {
         let clr = match Color::hex(&ent.color.clone()[1..]) {
             Ok(t) => t,
             Err(e) => {
@@ -199,12 +478,17 @@ fn setup(
             .insert(ent.uid as i32, col_mat);
     }
 
-    // add the LDtk object and the tile assets as resources and spawn a camera
-    let mut camera = OrthographicCameraBundle::new_2d();
-    camera.transform = Transform::from_translation(Vec3::new(0.0, 0.0, 50.0));
-    commands.insert_resource(map);
-    commands.insert_resource(visual_assets);
-    commands.spawn().insert_bundle(camera);
+    map.redraw = true;
+}
+
+// LDtk stores tileset paths relative to wherever the editor found them, which
+// for the bundled map is outside of the repository. Tilesets ship flat in
+// assets/, so only the file name is used to find them.
+fn tileset_asset_path(rel_path: &str) -> String {
+    match Path::new(rel_path).file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => rel_path.to_string(),
+    }
 }
 
 // Our update system runs every game loop and, if the tiles are not spawned, will spawn them.
@@ -213,18 +497,33 @@ fn setup(
 fn update(
     mut commands: Commands,
     mut map: ResMut<Map>,
+    projects: Res<Assets<LdtkProject>>,
     visual_assets: Res<VisualAssets>,
+    mut start: ResMut<PlayerStart>,
+    mut global: ResMut<GlobalGravity>,
+    mut move_to_start: EventWriter<MoveToStart>,
 ) {
     // If we don't need to redraw the tiles, go ahead and return (do nothing)
     if !map.redraw {
         return;
     }
 
+    let project = match projects.get(&map.handle) {
+        Some(project) => &project.0,
+        None => return,
+    };
+    let level = match map.level(project) {
+        Some(level) => level,
+        None => return,
+    };
+
+    // Levels without a PlayerStart must not keep the previous level's
+    *start = PlayerStart::default();
+
     // Add a background color. The "__bg_color" field should always be populated
     // with either the default background color or the level's custom color.
-    commands.insert_resource(ClearColor(
-        Color::hex(&map.ldtk_file.levels[0].bg_color[1..]).unwrap(),
-    ));
+    commands
+        .insert_resource(ClearColor(Color::hex(&level.bg_color[1..]).unwrap()));
 
     // For the current level, loop through the Layer Instances and start spawning
     // tiles. These Layer Instances can be one of four different kinds of layers:
@@ -240,7 +539,7 @@ fn update(
     //
     // Using .rev() allows us to handle things "bottom to top" and makes sorting
     // on the z-axis easier to reason about.
-    for (idx, layer) in map.ldtk_file.levels[map.current_level]
+    for (idx, layer) in level
         .layer_instances
         .as_ref()
         .unwrap()
@@ -262,7 +561,7 @@ fn update(
         // instance so we can easily pass it around to functions later.
         let layer_info = LayerInfo {
             grid_width: layer.c_wid as i32,
-            _grid_height: layer.c_hei as i32,
+            grid_height: layer.c_hei as i32,
             grid_cell_size: layer.grid_size as i32,
             z_index: 50 - idx as i32,
             px_width: layer.c_wid as f32
@@ -321,14 +620,89 @@ fn update(
                             "Generating IntGrid Layer w/ Color Materials: {}",
                             layer.identifier
                         );
-                        for tile in layer.int_grid_csv.iter() {
+                        // Materials are keyed by IntGrid value, 0 is an empty
+                        // cell and has none.
+                        let colors =
+                            &visual_assets.int_grid_materials[&layer_uid];
+                        for (coord_id, tile) in
+                            layer.int_grid_csv.iter().enumerate()
+                        {
+                            if let Some(material) = colors.get(tile) {
+                                display_color(
+                                    layer_info,
+                                    coord_id as i32,
+                                    &mut commands,
+                                    material.clone(),
+                                )
+                            }
+                        }
+                    }
+                }
+
+                // Whether or not the layer is drawn with tiles, its solid
+                // values become colliders.
+                let solid_values = int_grid_values(
+                    project,
+                    layer.layer_def_uid,
+                    SOLID_INT_GRID_VALUES,
+                );
+                let solid: Vec<bool> = layer
+                    .int_grid_csv
+                    .iter()
+                    .map(|value| solid_values.contains(value))
+                    .collect();
+                for rect in merge_cells(
+                    layer_info.grid_width,
+                    layer_info.grid_height,
+                    &solid,
+                ) {
+                    spawn_collider(
+                        layer_info,
+                        rect,
+                        ColliderType::Solid,
+                        &mut commands,
+                    );
+                }
+
+                // Hazards become sensors. The tileset rules don't know about
+                // them, so tiled layers draw them with their color too.
+                let hazard_values = int_grid_values(
+                    project,
+                    layer.layer_def_uid,
+                    HAZARD_INT_GRID_VALUES,
+                );
+                let hazard: Vec<bool> = layer
+                    .int_grid_csv
+                    .iter()
+                    .map(|value| hazard_values.contains(value))
+                    .collect();
+                for rect in merge_cells(
+                    layer_info.grid_width,
+                    layer_info.grid_height,
+                    &hazard,
+                ) {
+                    spawn_collider(
+                        layer_info,
+                        rect,
+                        ColliderType::Sensor,
+                        &mut commands,
+                    )
+                    .insert(Hazard);
+                }
+                if layer.tileset_def_uid.is_some() {
+                    let colors = &visual_assets.int_grid_materials[&layer_uid];
+                    for (coord_id, tile) in
+                        layer.int_grid_csv.iter().enumerate()
+                    {
+                        if !hazard_values.contains(tile) {
+                            continue;
+                        }
+                        if let Some(material) = colors.get(tile) {
                             display_color(
                                 layer_info,
-                                tile,
+                                coord_id as i32,
                                 &mut commands,
-                                visual_assets.int_grid_materials[&layer_uid]
-                                    [*tile as usize]
-                                    .clone(),
+                                material.clone(),
                             )
                         }
                     }
@@ -338,10 +712,22 @@ fn update(
                 println!("Generating Entities Layer: {}", layer.identifier);
                 // Entities reference their tiles and colors within the instances
                 for entity in layer.entity_instances.iter() {
+                    // Entities the game understands become game state
+                    // instead of being drawn.
+                    if spawn_game_entity(
+                        layer_info,
+                        entity,
+                        &mut commands,
+                        &mut start,
+                        &visual_assets,
+                    ) {
+                        continue;
+                    }
+
                     // we need some extra fields from the defs section of the
                     // JSON that aren't included in the entity instances.
                     let mut extra_ent_defs = ExtraEntDefs::new();
-                    for ent in map.ldtk_file.defs.entities.iter() {
+                    for ent in project.defs.entities.iter() {
                         if ent.uid == entity.def_uid {
                             extra_ent_defs.__tile_id = 0;
                             extra_ent_defs.__width = ent.width as i32;
@@ -351,7 +737,7 @@ fn update(
                             ldtk_rust::RenderMode::Tile => {
                                 extra_ent_defs.__tile_id =
                                     ent.tile_id.unwrap() as i32;
-                                for ts in map.ldtk_file.defs.tilesets.iter() {
+                                for ts in project.defs.tilesets.iter() {
                                     if ts.uid == ent.tileset_id.unwrap() {
                                         extra_ent_defs.__scale = ent.width
                                             as f32
@@ -380,6 +766,188 @@ fn update(
 
     // Whew, we've draw everyting so update the Map instance so we don't do it every game loop.
     map.redraw = false;
+    map.drawn = true;
+
+    commands.insert_resource(LevelBounds {
+        half_extents: Vec2::new(
+            level.px_wid as f32 * TILE_SCALE / 2.,
+            level.px_hei as f32 * TILE_SCALE / 2.,
+        ),
+    });
+
+    // Levels with the global_gravity field turn the whole world at once,
+    // starting out the way the PlayerStart points
+    global.enabled = level_field_bool(level, "global_gravity").unwrap_or(false);
+
+    if map.move_to_start {
+        global.gravity.set_angle(start.rotation);
+        move_to_start.send(MoveToStart);
+        map.move_to_start = false;
+    }
+}
+
+// Despawn the current level and queue the requested one to be drawn.
+fn change_level(
+    mut commands: Commands,
+    mut events: EventReader<ChangeLevel>,
+    mut map: ResMut<Map>,
+    projects: Res<Assets<LdtkProject>>,
+    level_entities: Query<Entity, With<LevelEntity>>,
+    mut point: ResMut<RespawnPoint>,
+    mut players: Query<(&mut RigidBodyPosition, &Gravity), With<Player>>,
+) {
+    // Only the last request in a frame matters
+    let event = match events.iter().last() {
+        Some(event) => event,
+        None => return,
+    };
+
+    let project = match projects.get(&map.handle) {
+        Some(project) => &project.0,
+        None => return,
+    };
+
+    let index = match level_index(project, &event.level) {
+        Some(index) => index,
+        None => {
+            println!("No level to change to");
+            return;
+        }
+    };
+
+    for entity in level_entities.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    match event.entry {
+        LevelEntry::Start => {
+            map.current_level = index;
+            map.move_to_start = true;
+        }
+        LevelEntry::Edge => {
+            // The player comes back to where they walked in
+            let to = &project.levels[index];
+            if let Some(from) = map.level(project) {
+                for (mut position, gravity) in players.iter_mut() {
+                    let world = level_to_world(
+                        from,
+                        position.position.translation.vector,
+                    );
+                    position.position.translation.vector =
+                        world_to_level(to, world);
+                    *point = RespawnPoint {
+                        position: position.position.translation.vector,
+                        rotation: gravity.target_angle,
+                        level: Some(index),
+                    };
+                }
+            }
+            map.current_level = index;
+        }
+        LevelEntry::Stay => {
+            map.current_level = index;
+        }
+    }
+
+    println!("Changing to level: {}", project.levels[index].identifier);
+    map.redraw = true;
+}
+
+// When the player walks out of the level, continue into the neighbouring
+// level on that side if there is one.
+fn cross_level_edge(
+    map: Res<Map>,
+    projects: Res<Assets<LdtkProject>>,
+    mut change_level: EventWriter<ChangeLevel>,
+    query: Query<&RigidBodyPosition, With<Player>>,
+) {
+    let project = match projects.get(&map.handle) {
+        Some(project) => &project.0,
+        None => return,
+    };
+    let level = match map.level(project) {
+        Some(level) => level,
+        None => return,
+    };
+    let half = level_half_extents(level);
+
+    for position in query.iter() {
+        let position = position.position.translation.vector;
+        let dir = if position.x > half.x {
+            "e"
+        } else if position.x < -half.x {
+            "w"
+        } else if position.y > half.y {
+            "n"
+        } else if position.y < -half.y {
+            "s"
+        } else {
+            continue;
+        };
+
+        // A side can have several neighbours, take the one the player is
+        // level with. Past the end of all of them the player stays here.
+        let world = level_to_world(level, position);
+        let across = |to: &&Level| {
+            let (along, start, size) = match dir {
+                "e" | "w" => (world.y, to.world_y, to.px_hei),
+                _ => (world.x, to.world_x, to.px_wid),
+            };
+            along >= start as f32 && along < (start + size) as f32
+        };
+        let neighbour = level
+            .neighbours
+            .iter()
+            .filter(|n| n.dir == dir)
+            .filter_map(|n| {
+                project.levels.iter().find(|to| to.uid == n.level_uid)
+            })
+            .find(across);
+
+        if let Some(neighbour) = neighbour {
+            change_level.send(ChangeLevel {
+                level: LevelTarget::Uid(neighbour.uid),
+                entry: LevelEntry::Edge,
+            });
+        }
+    }
+}
+
+// Jump straight to a level with the number keys, for testing levels.
+fn select_level(
+    actions: Res<Input<Action>>,
+    mut change_level: EventWriter<ChangeLevel>,
+) {
+    for (index, action) in LEVEL_ACTIONS.iter().enumerate() {
+        if actions.just_pressed(*action) {
+            change_level.send(ChangeLevel {
+                level: LevelTarget::Index(index),
+                entry: LevelEntry::Start,
+            });
+        }
+    }
+}
+
+// Follow an exit when the player touches it.
+fn enter_exit(
+    mut events: EventReader<IntersectionEvent>,
+    mut change_level: EventWriter<ChangeLevel>,
+    exits: Query<&Exit>,
+    players: Query<(), With<Player>>,
+) {
+    for event in events.iter().filter(|event| event.intersecting) {
+        let a = event.collider1.entity();
+        let b = event.collider2.entity();
+
+        for (exit, other) in [(a, b), (b, a)].iter() {
+            if let (Ok(exit), Ok(_)) = (exits.get(*exit), players.get(*other)) {
+                change_level.send(ChangeLevel {
+                    level: LevelTarget::Identifier(exit.level.clone()),
+                    entry: LevelEntry::Start,
+                });
+            }
+        }
+    }
 }
 
 // Spawn a tile. Check to see if it needs to flip on the x and/or y axis before spawning.
@@ -400,24 +968,220 @@ fn display_tile(
         }
         _ => (),
     }
-    commands.spawn().insert_bundle(SpriteSheetBundle {
-        transform: Transform {
-            translation: convert_to_world(
-                layer_info.px_width,
-                layer_info.px_height,
-                layer_info.grid_cell_size,
-                TILE_SCALE,
-                tile.px[0] as i32,
-                tile.px[1] as i32,
-                layer_info.z_index,
-            ),
-            rotation: flip(flip_x, flip_y),
-            scale: Vec3::splat(TILE_SCALE),
+    commands
+        .spawn()
+        .insert_bundle(SpriteSheetBundle {
+            transform: Transform {
+                translation: convert_to_world(
+                    layer_info.px_width,
+                    layer_info.px_height,
+                    layer_info.grid_cell_size,
+                    TILE_SCALE,
+                    tile.px[0] as i32,
+                    tile.px[1] as i32,
+                    layer_info.z_index,
+                ),
+                rotation: flip(flip_x, flip_y),
+                scale: Vec3::splat(TILE_SCALE),
+            },
+            sprite: TextureAtlasSprite::new(tile.t as u32),
+            texture_atlas: handle,
+            ..Default::default()
+        })
+        .insert(LevelEntity);
+}
+
+// Turn the LDtk entities that mean something to the game into game state.
+// Returns false for entities that are only decoration, which are drawn
+// by display_entity instead.
+fn spawn_game_entity(
+    layer_info: LayerInfo,
+    entity: &EntityInstance,
+    commands: &mut Commands,
+    start: &mut PlayerStart,
+    visual_assets: &VisualAssets,
+) -> bool {
+    match &entity.identifier[..] {
+        "PlayerStart" => {
+            *start = PlayerStart {
+                position: entity_center(layer_info, entity),
+                rotation: field_str(entity, "gravity")
+                    .map(direction_rotation)
+                    .unwrap_or(0.),
+                velocity: field_f32(entity, "velocity"),
+                jump_distance: field_f32(entity, "jump_distance"),
+                jump_height: field_f32(entity, "jump_height"),
+                heavy_scalar: field_f32(entity, "heavy_scalar"),
+            };
+            true
+        }
+        "Exit" => {
+            let level = match field_str(entity, "level") {
+                Some(level) => level.to_string(),
+                None => {
+                    println!("Exit without a level at {:?}", entity.grid);
+                    return false;
+                }
+            };
+            spawn_sensor(layer_info, entity, commands).insert(Exit { level });
+            // Exits are still drawn so the player can find them
+            false
+        }
+        "Checkpoint" => {
+            let position = entity_center(layer_info, entity);
+            spawn_sensor(layer_info, entity, commands).insert(Checkpoint {
+                position,
+                rotation: field_str(entity, "gravity").map(direction_rotation),
+            });
+            false
+        }
+        "CameraRoom" => {
+            let center = entity_center(layer_info, entity) * METERS_TO_PIXELS;
+            commands
+                .spawn()
+                .insert(CameraRoom {
+                    center: Vec2::new(center.x, center.y),
+                    half_extents: Vec2::new(
+                        entity.width as f32 * TILE_SCALE / 2.,
+                        entity.height as f32 * TILE_SCALE / 2.,
+                    ),
+                    zoom: field_f32(entity, "zoom").unwrap_or(1.),
+                })
+                .insert(LevelEntity);
+            true
+        }
+        "GravityZone" => {
+            spawn_sensor(layer_info, entity, commands).insert(GravityZone {
+                rotation: direction_rotation(
+                    field_str(entity, "direction").unwrap_or("Down"),
+                ),
+                strength: field_f32(entity, "strength").unwrap_or(1.),
+            });
+            false
+        }
+        "Crate" => {
+            let position = entity_center(layer_info, entity);
+            let width = entity.width as f32 / LDTK_PIXELS_PER_METER;
+            let height = entity.height as f32 / LDTK_PIXELS_PER_METER;
+            let material = visual_assets.entity_materials
+                [&(entity.def_uid as i32)]
+                .clone();
+            commands
+                .spawn_bundle(RigidBodyBundle {
+                    position: position.into(),
+                    forces: RigidBodyForces {
+                        gravity_scale: 0.,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert_bundle(ColliderBundle {
+                    shape: ColliderShape::cuboid(width / 2., height / 2.),
+                    ..Default::default()
+                })
+                .insert_bundle(SpriteBundle {
+                    material,
+                    sprite: Sprite::new(Vec2::new(
+                        width * METERS_TO_PIXELS,
+                        height * METERS_TO_PIXELS,
+                    )),
+                    transform: Transform::from_xyz(
+                        position.x * METERS_TO_PIXELS,
+                        position.y * METERS_TO_PIXELS,
+                        layer_info.z_index as f32,
+                    ),
+                    ..Default::default()
+                })
+                .insert(RigidBodyPositionSync::Discrete)
+                .insert(Gravity::default())
+                .insert(LevelEntity);
+            true
+        }
+        _ => false,
+    }
+}
+
+// A sensor covering the entity's rectangle that reports intersections.
+fn spawn_sensor<'a, 'b>(
+    layer_info: LayerInfo,
+    entity: &EntityInstance,
+    commands: &'b mut Commands<'a>,
+) -> EntityCommands<'a, 'b> {
+    let mut sensor = commands.spawn_bundle(ColliderBundle {
+        collider_type: ColliderType::Sensor,
+        shape: ColliderShape::cuboid(
+            entity.width as f32 / LDTK_PIXELS_PER_METER / 2.,
+            entity.height as f32 / LDTK_PIXELS_PER_METER / 2.,
+        ),
+        position: entity_center(layer_info, entity).into(),
+        flags: ColliderFlags {
+            active_events: ActiveEvents::INTERSECTION_EVENTS,
+            ..Default::default()
         },
-        sprite: TextureAtlasSprite::new(tile.t as u32),
-        texture_atlas: handle,
         ..Default::default()
     });
+    sensor.insert(LevelEntity);
+    sensor
+}
+
+// The center of an entity in meters. LDtk places entities by their pivot,
+// so the pivot is used to find the middle of the entity's rectangle.
+fn entity_center(
+    layer_info: LayerInfo,
+    entity: &EntityInstance,
+) -> Vector<f32> {
+    let x = entity.px[0] as f32
+        + (0.5 - entity.pivot[0] as f32) * entity.width as f32;
+    let y = entity.px[1] as f32
+        + (0.5 - entity.pivot[1] as f32) * entity.height as f32;
+    let world_x = (x * TILE_SCALE) - (layer_info.px_width / 2.);
+    let world_y = -(y * TILE_SCALE) + (layer_info.px_height / 2.);
+    [world_x / METERS_TO_PIXELS, world_y / METERS_TO_PIXELS].into()
+}
+
+// Read a Float field instance. Null values and missing fields are None.
+fn field_f32(entity: &EntityInstance, identifier: &str) -> Option<f32> {
+    entity
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| field.value.as_ref())
+        .and_then(|value| value.as_f64())
+        .map(|value| value as f32)
+}
+
+// Read a Bool field instance of a level.
+fn level_field_bool(level: &Level, identifier: &str) -> Option<bool> {
+    level
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| field.value.as_ref())
+        .and_then(|value| value.as_bool())
+}
+
+// Read a String or Enum field instance.
+fn field_str<'a>(
+    entity: &'a EntityInstance,
+    identifier: &str,
+) -> Option<&'a str> {
+    entity
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| field.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+// Convert a value of the LDtk Direction enum into the rotation that
+// Gravity needs to make gravity point that way.
+fn direction_rotation(direction: &str) -> f32 {
+    match direction {
+        "Left" => TAU / -4.,
+        "Up" => TAU / 2.,
+        "Right" => TAU / 4.,
+        _ => 0.,
+    }
 }
 
 // spawn your entities. This is likely very game dependant, but
@@ -435,86 +1199,205 @@ fn display_entity(
             let tileset_uid = t.tileset_uid as i32;
             let handle: Handle<TextureAtlas> =
                 visual_assets.spritesheets[&tileset_uid].clone();
-            commands.spawn().insert_bundle(SpriteSheetBundle {
-                transform: Transform {
-                    translation: convert_to_world(
-                        layer_info.px_width,
-                        layer_info.px_height,
-                        extra_ent_defs.__height,
-                        TILE_SCALE,
-                        entity.grid[0] as i32 * layer_info.grid_cell_size,
-                        entity.grid[1] as i32 * layer_info.grid_cell_size,
-                        layer_info.z_index,
+            commands
+                .spawn()
+                .insert_bundle(SpriteSheetBundle {
+                    transform: Transform {
+                        translation: convert_to_world(
+                            layer_info.px_width,
+                            layer_info.px_height,
+                            extra_ent_defs.__height,
+                            TILE_SCALE,
+                            entity.grid[0] as i32 * layer_info.grid_cell_size,
+                            entity.grid[1] as i32 * layer_info.grid_cell_size,
+                            layer_info.z_index,
+                        ),
+                        scale: Vec3::splat(extra_ent_defs.__scale * TILE_SCALE),
+                        ..Default::default()
+                    },
+                    sprite: TextureAtlasSprite::new(
+                        extra_ent_defs.__tile_id as u32,
                     ),
-                    scale: Vec3::splat(extra_ent_defs.__scale * TILE_SCALE),
+                    texture_atlas: handle,
                     ..Default::default()
-                },
-                sprite: TextureAtlasSprite::new(
-                    extra_ent_defs.__tile_id as u32,
-                ),
-                texture_atlas: handle,
-                ..Default::default()
-            });
+                })
+                .insert(LevelEntity);
         }
         None => {
             // process color shape
             let handle: Handle<ColorMaterial> = visual_assets.entity_materials
                 [&(entity.def_uid as i32)]
                 .clone();
-            commands.spawn().insert_bundle(SpriteBundle {
-                material: handle,
-                sprite: Sprite::new(Vec2::new(
-                    extra_ent_defs.__width as f32,
-                    extra_ent_defs.__height as f32,
-                )),
-                transform: Transform {
-                    translation: convert_to_world(
-                        layer_info.px_width,
-                        layer_info.px_height,
-                        extra_ent_defs.__height,
-                        TILE_SCALE,
-                        entity.grid[0] as i32 * layer_info.grid_cell_size,
-                        entity.grid[1] as i32 * layer_info.grid_cell_size,
-                        layer_info.z_index,
-                    ),
-                    scale: Vec3::splat(TILE_SCALE),
+            commands
+                .spawn()
+                .insert_bundle(SpriteBundle {
+                    material: handle,
+                    sprite: Sprite::new(Vec2::new(
+                        extra_ent_defs.__width as f32,
+                        extra_ent_defs.__height as f32,
+                    )),
+                    transform: Transform {
+                        translation: convert_to_world(
+                            layer_info.px_width,
+                            layer_info.px_height,
+                            extra_ent_defs.__height,
+                            TILE_SCALE,
+                            entity.grid[0] as i32 * layer_info.grid_cell_size,
+                            entity.grid[1] as i32 * layer_info.grid_cell_size,
+                            layer_info.z_index,
+                        ),
+                        scale: Vec3::splat(TILE_SCALE),
+                        ..Default::default()
+                    },
                     ..Default::default()
-                },
-                ..Default::default()
-            });
+                })
+                .insert(LevelEntity);
         }
     }
 }
 
 fn display_color(
     layer_info: LayerInfo,
-    tile: &i64,
+    coord_id: i32,
     commands: &mut Commands,
     handle: Handle<ColorMaterial>,
 ) {
-    let x = *tile as i32 % layer_info.grid_width;
-    let y = *tile as i32 / layer_info.grid_width;
-    commands.spawn().insert_bundle(SpriteBundle {
-        material: handle,
-        sprite: Sprite::new(Vec2::new(
-            layer_info.grid_cell_size as f32,
-            layer_info.grid_cell_size as f32,
-        )),
-        transform: Transform {
-            translation: convert_to_world(
-                layer_info.px_width,
-                layer_info.px_height,
-                layer_info.grid_cell_size,
-                TILE_SCALE,
-                x * layer_info.grid_cell_size,
-                y as i32 * layer_info.grid_cell_size,
-                layer_info.z_index,
-            ),
-            scale: Vec3::splat(TILE_SCALE),
+    let x = coord_id % layer_info.grid_width;
+    let y = coord_id / layer_info.grid_width;
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            material: handle,
+            sprite: Sprite::new(Vec2::new(
+                layer_info.grid_cell_size as f32,
+                layer_info.grid_cell_size as f32,
+            )),
+            transform: Transform {
+                translation: convert_to_world(
+                    layer_info.px_width,
+                    layer_info.px_height,
+                    layer_info.grid_cell_size,
+                    TILE_SCALE,
+                    x * layer_info.grid_cell_size,
+                    y as i32 * layer_info.grid_cell_size,
+                    layer_info.z_index,
+                ),
+                scale: Vec3::splat(TILE_SCALE),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(LevelEntity);
+}
+
+// Collect the IntGrid values of a layer definition whose identifiers are
+// in the list.
+fn int_grid_values(
+    project: &Project,
+    layer_def_uid: i64,
+    identifiers: &[&str],
+) -> Vec<i64> {
+    project
+        .defs
+        .layers
+        .iter()
+        .filter(|layer| layer.uid == layer_def_uid)
+        .flat_map(|layer| layer.int_grid_values.iter())
+        .filter(|value| match &value.identifier {
+            Some(identifier) => identifiers.contains(&identifier.as_str()),
+            None => false,
+        })
+        .map(|value| value.value)
+        .collect()
+}
+
+// Greedily merge solid cells into rectangles. Each rectangle grows as far
+// right as it can along its first row and then as far down as every cell
+// beneath that row is also solid and unclaimed, which keeps the number of
+// colliders close to the number of distinct shapes in the level.
+fn merge_cells(width: i32, height: i32, solid: &[bool]) -> Vec<CellRect> {
+    let index = |x: i32, y: i32| (y * width + x) as usize;
+    let mut claimed = vec![false; solid.len()];
+    let mut rects = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            if !solid[index(x, y)] || claimed[index(x, y)] {
+                continue;
+            }
+
+            let mut rect_width = 1;
+            while x + rect_width < width
+                && solid[index(x + rect_width, y)]
+                && !claimed[index(x + rect_width, y)]
+            {
+                rect_width += 1;
+            }
+
+            let mut rect_height = 1;
+            while y + rect_height < height
+                && (x..x + rect_width).all(|cx| {
+                    solid[index(cx, y + rect_height)]
+                        && !claimed[index(cx, y + rect_height)]
+                })
+            {
+                rect_height += 1;
+            }
+
+            for cy in y..y + rect_height {
+                for cx in x..x + rect_width {
+                    claimed[index(cx, cy)] = true;
+                }
+            }
+
+            rects.push(CellRect {
+                x,
+                y,
+                width: rect_width,
+                height: rect_height,
+            });
+        }
+    }
+
+    rects
+}
+
+// Spawn a static collider covering a rectangle of cells. Rapier works in
+// meters, so the world pixel position is divided by METERS_TO_PIXELS.
+// Sensors report intersections.
+fn spawn_collider<'a, 'b>(
+    layer_info: LayerInfo,
+    rect: CellRect,
+    collider_type: ColliderType,
+    commands: &'b mut Commands<'a>,
+) -> EntityCommands<'a, 'b> {
+    let cell_size = layer_info.grid_cell_size as f32 * TILE_SCALE;
+    let width = rect.width as f32 * cell_size;
+    let height = rect.height as f32 * cell_size;
+    let x =
+        (rect.x as f32 * cell_size) + (width / 2.) - (layer_info.px_width / 2.);
+    let y = -(rect.y as f32 * cell_size) - (height / 2.)
+        + (layer_info.px_height / 2.);
+
+    let active_events = match collider_type {
+        ColliderType::Sensor => ActiveEvents::INTERSECTION_EVENTS,
+        ColliderType::Solid => ActiveEvents::empty(),
+    };
+    let mut collider = commands.spawn_bundle(ColliderBundle {
+        collider_type,
+        shape: ColliderShape::cuboid(
+            width / 2. / METERS_TO_PIXELS,
+            height / 2. / METERS_TO_PIXELS,
+        ),
+        position: [x / METERS_TO_PIXELS, y / METERS_TO_PIXELS].into(),
+        flags: ColliderFlags {
+            active_events,
             ..Default::default()
         },
         ..Default::default()
     });
+    collider.insert(LevelEntity);
+    collider
 }
 
 // LDtk provides pixel locations starting in the top left. For Bevy we need to
@@ -549,3 +1432,84 @@ fn flip(x: bool, y: bool) -> Quat {
     }
     q1 * q2
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse rows of '#' (solid) and '.' (empty) into merge_cells arguments
+    fn grid(rows: &[&str]) -> (i32, i32, Vec<bool>) {
+        let solid = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|cell| cell == '#'))
+            .collect();
+        (rows[0].len() as i32, rows.len() as i32, solid)
+    }
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> CellRect {
+        CellRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn single_cell() {
+        let (width, height, solid) = grid(&["...", ".#.", "..."]);
+        assert_eq!(merge_cells(width, height, &solid), vec![rect(1, 1, 1, 1)]);
+    }
+
+    #[test]
+    fn empty_grid() {
+        let (width, height, solid) = grid(&["..", ".."]);
+        assert!(merge_cells(width, height, &solid).is_empty());
+    }
+
+    #[test]
+    fn full_grid() {
+        let (width, height, solid) = grid(&["###", "###"]);
+        assert_eq!(merge_cells(width, height, &solid), vec![rect(0, 0, 3, 2)]);
+    }
+
+    #[test]
+    fn l_shape() {
+        let (width, height, solid) = grid(&["#..", "#..", "###"]);
+        assert_eq!(
+            merge_cells(width, height, &solid),
+            vec![rect(0, 0, 1, 3), rect(1, 2, 2, 1)]
+        );
+    }
+
+    #[test]
+    fn holes() {
+        let (width, height, solid) = grid(&["###", "#.#", "###"]);
+        assert_eq!(
+            merge_cells(width, height, &solid),
+            vec![
+                rect(0, 0, 3, 1),
+                rect(0, 1, 1, 2),
+                rect(2, 1, 1, 2),
+                rect(1, 2, 1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn covers_every_solid_cell_once() {
+        let (width, height, solid) =
+            grid(&["##..#", "##.##", "#####", "..#.."]);
+        let mut covered = vec![0; solid.len()];
+        for rect in merge_cells(width, height, &solid) {
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+        let expected: Vec<i32> =
+            solid.iter().map(|cell| *cell as i32).collect();
+        assert_eq!(covered, expected);
+    }
+}
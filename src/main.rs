@@ -1,20 +1,47 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+mod camera;
+mod checkpoint;
+mod contact;
+mod dash;
+mod death;
+mod debug;
+mod gravity;
+mod input;
+mod kinematic;
 mod ldtk;
 mod map;
 mod player;
+mod screen;
+mod tuning;
 
 pub const METERS_TO_PIXELS: f32 = 12.; // 10px is 1m
 
 pub const WINDOW_WIDTH: f32 = 80.;
 pub const WINDOW_HEIGHT: f32 = 60.;
 
+// Runs before the other startup stages, where assets start loading
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+struct WatchAssets;
+
 fn main() {
     App::build()
         .add_plugins(DefaultPlugins)
+        .add_startup_stage_before(
+            StartupStage::PreStartup,
+            WatchAssets,
+            SystemStage::single_threaded(),
+        )
+        .add_startup_system_to_stage(WatchAssets, watch_assets.system())
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        // .add_plugin(ldtk::MapPlugin)
+        .add_plugin(ldtk::MapPlugin)
+        .add_plugin(tuning::TuningPlugin)
+        .add_plugin(debug::DebugPlugin)
+        .add_plugin(kinematic::KinematicPlugin)
+        .add_plugin(camera::CameraPlugin)
+        .add_plugin(screen::ScreenPlugin)
+        .add_plugin(input::InputPlugin)
         .insert_resource(WindowDescriptor {
             title: "Donut".to_string(),
             width: WINDOW_WIDTH * METERS_TO_PIXELS,
@@ -27,26 +54,114 @@ fn main() {
             ..Default::default()
         })
         .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
+        .insert_resource(player::PlayerStart::default())
+        .insert_resource(gravity::GlobalGravity::default())
+        .insert_resource(checkpoint::RespawnPoint::default())
+        .insert_resource(death::DeathCount::default())
+        .add_event::<player::MoveToStart>()
+        .add_event::<dash::DashEvent>()
+        .add_event::<checkpoint::Respawn>()
+        .add_event::<death::Death>()
         .add_startup_system(setup.system())
-        .add_startup_system(map::spawn_ground.system())
         .add_startup_system(player::spawn_player.system())
-        .add_system(bevy::input::system::exit_on_esc_system.system())
-        .add_system(player::player_jump.system())
-        .add_system(player::player_move.system())
-        .add_system(player::respawn.system())
-        .add_system(player::rotate.system())
-        .add_system(player::link_physics.system())
+        .add_system(map::spawn_ground.system())
+        .add_system(dash::dash_color.system())
+        .add_system(
+            player::move_to_start
+                .system()
+                .label(player::PlayerSystem::Velocity),
+        )
+        .add_system(checkpoint::start_checkpoint.system())
+        .add_system(checkpoint::reach_checkpoint.system())
+        .add_system(
+            checkpoint::respawn
+                .system()
+                .label(player::PlayerSystem::Velocity)
+                .before(ldtk::MapSystem::ChangeLevel),
+        )
+        // Gameplay stops while the game is paused
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(input::playing.system())
+                .with_system(
+                    contact::detect_contacts
+                        .system()
+                        .label(player::PlayerSystem::Contacts),
+                )
+                .with_system(
+                    player::player_jump
+                        .system()
+                        .label(player::PlayerSystem::Jump)
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::Contacts),
+                )
+                .with_system(
+                    player::player_move
+                        .system()
+                        .label(player::PlayerSystem::Move)
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::Jump),
+                )
+                .with_system(
+                    player::wall_slide
+                        .system()
+                        .label(player::PlayerSystem::WallSlide)
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::Move),
+                )
+                .with_system(
+                    player::snap_to_ground
+                        .system()
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::Dash),
+                )
+                .with_system(
+                    player::stick_to_slope
+                        .system()
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::Contacts)
+                        .before(player::PlayerSystem::Dash),
+                )
+                .with_system(player::hard_landing.system())
+                .with_system(
+                    dash::dash
+                        .system()
+                        .label(player::PlayerSystem::Dash)
+                        .label(player::PlayerSystem::Velocity)
+                        .after(player::PlayerSystem::WallSlide),
+                )
+                .with_system(checkpoint::restart.system())
+                .with_system(death::touch_hazard.system())
+                .with_system(death::out_of_bounds.system())
+                .with_system(death::die.system())
+                .with_system(death::dying.system())
+                .with_system(player::rotate.system())
+                .with_system(gravity::turn_gravity.system())
+                .with_system(player::align_player.system())
+                .with_system(gravity::enter_gravity_zone.system())
+                .with_system(player::player_gravity.system())
+                .with_system(gravity::link_physics.system()),
+        )
 //        .add_system(player::limit_velocity.system())
-        .add_system(player::follow_player.system())
         .run();
 }
 
 pub struct MainCamera;
 
+// Reload the LDtk project and the tuning file when they change on disk.
+// Every call replaces the watcher and forgets the files it was watching, so
+// this must be the only one and come before anything is loaded.
+fn watch_assets(asset_server: Res<AssetServer>) {
+    if let Err(e) = asset_server.watch_for_changes() {
+        println!("Error: {:?}", e);
+    }
+}
+
 fn setup(mut commands: Commands) {
     commands
         .spawn_bundle(OrthographicCameraBundle::new_2d())
-        .insert(MainCamera);
+        .insert(MainCamera)
+        .insert(camera::CameraFollow::default());
     commands.spawn_bundle(UiCameraBundle::default());
 }
 
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+use crate::ldtk::Map;
 use crate::{METERS_TO_PIXELS, WINDOW_WIDTH};
 
 pub const GROUND: f32 = -28.;
@@ -8,10 +9,26 @@ pub const GROUND: f32 = -28.;
 const GROUND_HEIGHT: f32 = 2.;
 const GROUND_WIDTH: f32 = WINDOW_WIDTH;
 
+// Fallback playfield for when there is no LDtk file or it fails to load.
 pub fn spawn_ground(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    map: Option<Res<Map>>,
+    mut spawned: Local<bool>,
 ) {
+    if *spawned {
+        return;
+    }
+
+    if let Some(map) = map {
+        if !map.load_failed(&asset_server) {
+            return;
+        }
+        println!("Using the fallback ground");
+    }
+    *spawned = true;
+
     /* Create the ground. */
     commands
         .spawn_bundle(ColliderBundle {
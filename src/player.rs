@@ -1,22 +1,195 @@
 use bevy::prelude::*;
 use bevy_rapier2d::na::Rotation2;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::TAU;
 
-use crate::{MainCamera, METERS_TO_PIXELS};
+use crate::camera::Shake;
+use crate::contact::{is_solid, Contacts};
+use crate::dash::Dash;
+use crate::gravity::{GlobalGravity, Gravity};
+use crate::input::Action;
+use crate::kinematic::Kinematic;
+use crate::tuning::PlayerTuning;
+use crate::METERS_TO_PIXELS;
 
 // Constants for physics (Units are meters and seconds)
 pub const PLAYER_HEIGHT: f32 = 1.5;
 pub const PLAYER_WIDTH: f32 = 1.5;
 
+pub const PLAYER_COLOR: Color = Color::rgb(0.4, 0.4, 0.5);
+
 pub const BOUNDARY: f32 = 100.;
 
+// Draw the player above every LDtk layer
+const PLAYER_Z: f32 = 100.;
+
+// Physics::new parameters used when the tuning file doesn't set them
+pub const DEFAULT_VELOCITY: f32 = 50.;
+pub const DEFAULT_JUMP_DISTANCE: f32 = 20.;
+pub const DEFAULT_JUMP_HEIGHT: f32 = 20.;
+pub const DEFAULT_HEAVY_SCALAR: f32 = 2.;
+
+pub const DEFAULT_FRICTION: f32 = 0.3;
+pub const DEFAULT_RESTITUTION: f32 = 0.3;
+
+// Jump forgiveness windows in seconds
+const DEFAULT_COYOTE_TIME: f32 = 0.1;
+const DEFAULT_JUMP_BUFFER: f32 = 0.15;
+
+// Wall slide and wall jump (Units are meters and seconds)
+const DEFAULT_WALL_SLIDE_VELOCITY: f32 = 20.;
+const DEFAULT_WALL_JUMP_VELOCITY: f32 = 50.;
+const DEFAULT_WALL_JUMP_LOCK: f32 = 0.15;
+
+// Horizontal speed changes in meters per second squared
+const DEFAULT_GROUND_ACCELERATION: f32 = 400.;
+const DEFAULT_GROUND_DECELERATION: f32 = 500.;
+const DEFAULT_GROUND_TURN: f32 = 800.;
+const DEFAULT_AIR_ACCELERATION: f32 = 250.;
+const DEFAULT_AIR_DECELERATION: f32 = 100.;
+const DEFAULT_AIR_TURN: f32 = 400.;
+
+// Slopes, in radians from up and meters
+pub const DEFAULT_MAX_SLOPE: f32 = TAU / 8.;
+const DEFAULT_SNAP_DISTANCE: f32 = 0.5;
+const DEFAULT_STEP_OFFSET: f32 = 0.5;
+
+// Meters, rounds the corners of the player's collider
+const PLAYER_CORNER_RADIUS: f32 = 0.1;
+
+// Seconds after walking off a floor where the player still snaps to one
+// below it
+const SNAP_TIME: f32 = 0.05;
+
+// Landing faster than this shakes the camera, up to HARD_LANDING_TRAUMA at
+// twice the speed (Units are meters and seconds)
+const HARD_LANDING_VELOCITY: f32 = 40.;
+const HARD_LANDING_TRAUMA: f32 = 0.5;
+
+// Dash (Units are meters and seconds)
+const DEFAULT_DASH_VELOCITY: f32 = 120.;
+const DEFAULT_DASH_DURATION: f32 = 0.15;
+const DEFAULT_DASH_COOLDOWN: f32 = 0.3;
+const DEFAULT_DASH_CHARGES: u32 = 1;
+
 pub struct Player;
 
-pub struct Physics {
-    // Basis has the columns [right, up]
-    pub basis: Matrix<f32>,
+// How the player's body is moved. Dynamic bodies are pushed around by
+// Rapier's solver, Kinematic ones by move_and_slide.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum Controller {
+    #[default]
+    Dynamic,
+    Kinematic,
+}
+
+impl Controller {
+    pub fn body_type(self) -> RigidBodyType {
+        match self {
+            Controller::Dynamic => RigidBodyType::Dynamic,
+            Controller::Kinematic => RigidBodyType::KinematicPositionBased,
+        }
+    }
+}
+
+// The player's systems run in this order: Contacts, Jump, Move, WallSlide,
+// then Dash, so a dash always wins over the velocity the others set.
+// snap_to_ground runs last, once the velocity is settled.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum PlayerSystem {
+    Contacts,
+    Jump,
+    Move,
+    WallSlide,
+    Dash,
+    // Every system that sets the player's velocity
+    Velocity,
+}
+
+// The timers behind coyote time and jump buffering
+#[derive(Default)]
+pub struct Jump {
+    // Seconds since the player last stood on the ground
+    pub air_time: f32,
+    // Seconds left before a buffered jump press is forgotten
+    pub buffered: f32,
+    // Seconds left where player_move leaves a wall jump alone
+    pub wall_lock: f32,
+}
+
+// Where and how the player appears. The LDtk PlayerStart entity replaces
+// this resource when a level is drawn; without a level, or in a level
+// without one, the player starts at the origin.
+pub struct PlayerStart {
+    pub position: Vector<f32>,
+    // Radians passed to Gravity::set_angle
+    pub rotation: f32,
+
+    pub velocity: Option<f32>,
+    pub jump_distance: Option<f32>,
+    pub jump_height: Option<f32>,
+    pub heavy_scalar: Option<f32>,
+}
+
+impl Default for PlayerStart {
+    fn default() -> Self {
+        PlayerStart {
+            position: [0., 0.].into(),
+            rotation: 0.,
+            velocity: None,
+            jump_distance: None,
+            jump_height: None,
+            heavy_scalar: None,
+        }
+    }
+}
+
+// Send this event to put the player back at the PlayerStart
+pub struct MoveToStart;
+
+impl PlayerStart {
+    // The tuning with this start's overrides applied
+    pub fn physics(&self, tuning: &PlayerTuning) -> Physics {
+        let mut physics = Physics::new(
+            self.velocity.unwrap_or(tuning.velocity),
+            self.jump_distance.unwrap_or(tuning.jump_distance),
+            self.jump_height.unwrap_or(tuning.jump_height),
+            self.heavy_scalar.unwrap_or(tuning.heavy_scalar),
+        );
+        physics.coyote_time = tuning.coyote_time;
+        physics.jump_buffer = tuning.jump_buffer;
+        physics.ground_acceleration = tuning.ground_acceleration;
+        physics.ground_deceleration = tuning.ground_deceleration;
+        physics.ground_turn = tuning.ground_turn;
+        physics.air_acceleration = tuning.air_acceleration;
+        physics.air_deceleration = tuning.air_deceleration;
+        physics.air_turn = tuning.air_turn;
+        physics.max_slope = tuning.max_slope.to_radians();
+        physics.snap_distance = tuning.snap_distance;
+        physics.step_offset = tuning.step_offset;
+        physics.wall_slide_velocity = tuning.wall_slide_velocity;
+        physics.wall_jump_velocity = tuning.wall_jump_velocity;
+        physics.wall_jump_lock = tuning.wall_jump_lock;
+        physics.dash_velocity = tuning.dash_velocity;
+        physics.dash_duration = tuning.dash_duration;
+        physics.dash_cooldown = tuning.dash_cooldown;
+        physics.dash_charges = tuning.dash_charges;
+        physics
+    }
+
+    pub fn gravity(&self, tuning: &PlayerTuning) -> Gravity {
+        let mut gravity = Gravity::new(self.physics(tuning).gravity);
+        gravity.rotation_duration = tuning.rotation_duration;
+        gravity.set_angle(self.rotation);
+        gravity
+    }
+}
 
+// The player controller. Movement and jumps are relative to the basis of
+// the player's Gravity, whose strength is kept at gravity by
+// player_gravity.
+pub struct Physics {
     pub velocity: f32,
     pub jump_distance: f32,
     pub jump_height: f32,
@@ -24,6 +197,41 @@ pub struct Physics {
     pub jump_velocity: f32,
     pub jump_gravity: f32,
     pub gravity: f32,
+
+    // Seconds after leaving the ground where a jump is still allowed
+    pub coyote_time: f32,
+    // Seconds a jump press is remembered before landing
+    pub jump_buffer: f32,
+
+    // How fast the local x velocity moves towards the held direction when
+    // speeding up, slowing down or turning around
+    pub ground_acceleration: f32,
+    pub ground_deceleration: f32,
+    pub ground_turn: f32,
+    pub air_acceleration: f32,
+    pub air_deceleration: f32,
+    pub air_turn: f32,
+
+    // Radians from up of the steepest floor the player can stand on
+    pub max_slope: f32,
+    // How far down the player sticks to the floor when walking down slopes
+    pub snap_distance: f32,
+    // Highest ledge the kinematic controller walks up without jumping
+    pub step_offset: f32,
+
+    // Fastest fall while pressing into a wall
+    pub wall_slide_velocity: f32,
+    // Speed a wall jump pushes away from the wall with
+    pub wall_jump_velocity: f32,
+    // Seconds after a wall jump before moving takes over again
+    pub wall_jump_lock: f32,
+
+    pub dash_velocity: f32,
+    // Seconds a dash lasts and then has to wait before the next one
+    pub dash_duration: f32,
+    pub dash_cooldown: f32,
+    // Dashes allowed before touching the ground again
+    pub dash_charges: u32,
 }
 
 impl Physics {
@@ -33,7 +241,7 @@ impl Physics {
         jump_height: f32,
         heavy_scalar: f32,
     ) -> Self {
-        return Physics {
+        let mut physics = Physics {
             // Definition
             velocity,
             jump_distance,
@@ -41,79 +249,141 @@ impl Physics {
             heavy_scalar,
 
             // Derived
-            basis: Matrix::new(1., 0., 0., 1.),
-            jump_velocity: (2. * jump_height * velocity) / (jump_distance / 2.),
-            jump_gravity: (-2. * jump_height * velocity * velocity)
-                / ((jump_distance / 2.) * (jump_distance / 2.)),
-            gravity: (-2. * jump_height * velocity * velocity)
-                / ((jump_distance / 2.) * (jump_distance / 2.))
-                * heavy_scalar,
+            jump_velocity: 0.,
+            jump_gravity: 0.,
+            gravity: 0.,
+
+            coyote_time: DEFAULT_COYOTE_TIME,
+            jump_buffer: DEFAULT_JUMP_BUFFER,
+
+            ground_acceleration: DEFAULT_GROUND_ACCELERATION,
+            ground_deceleration: DEFAULT_GROUND_DECELERATION,
+            ground_turn: DEFAULT_GROUND_TURN,
+            air_acceleration: DEFAULT_AIR_ACCELERATION,
+            air_deceleration: DEFAULT_AIR_DECELERATION,
+            air_turn: DEFAULT_AIR_TURN,
+
+            max_slope: DEFAULT_MAX_SLOPE,
+            snap_distance: DEFAULT_SNAP_DISTANCE,
+            step_offset: DEFAULT_STEP_OFFSET,
+
+            wall_slide_velocity: DEFAULT_WALL_SLIDE_VELOCITY,
+            wall_jump_velocity: DEFAULT_WALL_JUMP_VELOCITY,
+            wall_jump_lock: DEFAULT_WALL_JUMP_LOCK,
+
+            dash_velocity: DEFAULT_DASH_VELOCITY,
+            dash_duration: DEFAULT_DASH_DURATION,
+            dash_cooldown: DEFAULT_DASH_COOLDOWN,
+            dash_charges: DEFAULT_DASH_CHARGES,
         };
+        physics.derive();
+        physics
     }
 
-    pub fn rotate(mut self: &mut Self, radians: f32) {
-        let rotation = Rotation2::new(radians);
-        self.basis = self.basis * rotation;
+    // Recompute jump_velocity, jump_gravity and gravity from the
+    // definition. Call this after changing any of the definition fields.
+    pub fn derive(&mut self) {
+        let half_distance = self.jump_distance / 2.;
+        self.jump_velocity =
+            (2. * self.jump_height * self.velocity) / half_distance;
+        self.jump_gravity =
+            (-2. * self.jump_height * self.velocity * self.velocity)
+                / (half_distance * half_distance);
+        self.gravity = self.jump_gravity * self.heavy_scalar;
     }
+}
 
-    pub fn get_gravity(&self) -> Vector<f32> {
-        let scale: Vector<f32> = [0., self.gravity].into();
-        self.basis * scale
-    }
+// A box width wide and height tall with slightly rounded corners, so it
+// slides over seams between colliders without catching on them
+pub fn player_shape(width: f32, height: f32) -> ColliderShape {
+    let half_width = width / 2.;
+    let half_height = height / 2.;
+    let radius = PLAYER_CORNER_RADIUS.min(half_width).min(half_height);
+    ColliderShape::round_cuboid(
+        half_width - radius,
+        half_height - radius,
+        radius,
+    )
 }
 
 pub fn spawn_player(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    start: Res<PlayerStart>,
+    tuning: Res<PlayerTuning>,
 ) {
-    let physics = Physics::new(50., 20., 20., 2.);
+    let physics = start.physics(&tuning);
+    let gravity = start.gravity(&tuning);
+    let contacts = Contacts {
+        max_slope: physics.max_slope,
+        ..Default::default()
+    };
     let rigid_body = RigidBodyBundle {
+        body_type: tuning.controller.body_type(),
+        position: start.position.into(),
         ccd: RigidBodyCcd {
             ccd_enabled: true,
             ..Default::default()
         },
         forces: RigidBodyForces {
             gravity_scale: 0.,
-            force: physics.get_gravity(),
             ..Default::default()
         },
         mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
         ..Default::default()
     };
     let collider = ColliderBundle {
-        shape: ColliderShape::ball(PLAYER_HEIGHT / 2.),
+        shape: player_shape(tuning.width, tuning.height),
+        // Kinematic players need to touch the static level and its sensors
+        flags: ColliderFlags {
+            active_collision_types: ActiveCollisionTypes::default()
+                | ActiveCollisionTypes::KINEMATIC_STATIC,
+            ..Default::default()
+        },
         material: ColliderMaterial {
-            friction: 0.3,
+            friction: tuning.friction,
             friction_combine_rule: CoefficientCombineRule::Min.into(),
-            restitution: 0.3,
+            restitution: tuning.restitution,
             restitution_combine_rule: CoefficientCombineRule::Min.into(),
             ..Default::default()
         },
         ..Default::default()
     };
     let sprite = SpriteBundle {
-        material: materials.add(Color::rgb(0.4, 0.4, 0.5).into()),
+        material: materials.add(PLAYER_COLOR.into()),
         sprite: Sprite::new(Vec2::new(
-            PLAYER_WIDTH * METERS_TO_PIXELS,
-            PLAYER_HEIGHT * METERS_TO_PIXELS,
+            tuning.width * METERS_TO_PIXELS,
+            tuning.height * METERS_TO_PIXELS,
         )),
-        transform: Transform::from_xyz(0., 0., 5.),
+        transform: Transform::from_xyz(
+            start.position.x * METERS_TO_PIXELS,
+            start.position.y * METERS_TO_PIXELS,
+            PLAYER_Z,
+        ),
         ..Default::default()
     };
-    commands
-        .spawn_bundle(rigid_body)
+    let mut player = commands.spawn_bundle(rigid_body);
+    if tuning.controller == Controller::Kinematic {
+        player.insert(Kinematic);
+    }
+    player
         .insert_bundle(collider)
         .insert_bundle(sprite)
         .insert(RigidBodyPositionSync::Discrete)
         .insert(physics)
+        .insert(gravity)
+        .insert(contacts)
+        .insert(Jump::default())
+        .insert(Dash::default())
         .insert(Player);
 }
 
-pub fn link_physics(
-    mut query: Query<(&mut RigidBodyForces, &mut Physics), With<Player>>,
+// The player's gravity is whatever its jump calls for
+pub fn player_gravity(
+    mut query: Query<(&Physics, &mut Gravity), With<Player>>,
 ) {
-    for (mut forces, physics) in query.iter_mut() {
-        forces.force = physics.get_gravity();
+    for (physics, mut gravity) in query.iter_mut() {
+        gravity.strength = physics.gravity;
     }
 }
 
@@ -125,90 +395,319 @@ pub fn link_physics(
 //     }
 // }
 
+type JumpQuery<'a> = (
+    &'a mut Physics,
+    &'a Gravity,
+    &'a Contacts,
+    &'a Dash,
+    &'a mut Jump,
+    &'a mut RigidBodyVelocity,
+);
+
 pub fn player_jump(
-    kb: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Physics, &mut RigidBodyVelocity), With<Player>>,
+    time: Res<Time>,
+    actions: Res<Input<Action>>,
+    mut query: Query<JumpQuery, With<Player>>,
 ) {
-    for (mut physics, mut velocity) in query.iter_mut() {
-        if kb.just_pressed(KeyCode::Space) {
-            let decomposition = physics.basis.lu();
-            let x = decomposition.solve(&velocity.linvel).expect("Ooops!");
-            if x.y < physics.jump_velocity {
-                let movement: Vector<f32> = [x.x, physics.jump_velocity].into();
-                physics.gravity = physics.jump_gravity;
-                velocity.linvel = physics.basis * movement;
-            }
-        }
-
-        if kb.just_released(KeyCode::Space) {
+    let delta = time.delta_seconds();
+
+    for (mut physics, gravity, contacts, dash, mut jump, mut velocity) in
+        query.iter_mut()
+    {
+        if contacts.grounded() {
+            jump.air_time = 0.;
+        } else {
+            jump.air_time += delta;
+        }
+
+        if actions.just_pressed(Action::Jump) {
+            jump.buffered = physics.jump_buffer;
+        } else {
+            jump.buffered = (jump.buffered - delta).max(0.);
+        }
+        jump.wall_lock = (jump.wall_lock - delta).max(0.);
+
+        // The dash owns the velocity, a buffered jump waits for it to end
+        if dash.dashing() {
+            continue;
+        }
+
+        if actions.just_released(Action::Jump) {
             physics.gravity = physics.jump_gravity * physics.heavy_scalar;
         }
+
+        let decomposition = gravity.basis.lu();
+        let x = decomposition.solve(&velocity.linvel).expect("Ooops!");
+
+        // Bumping into a ceiling ends the rise instead of sliding along it,
+        // and falls as if the jump was released
+        if contacts.ceiling && x.y > 0. {
+            velocity.linvel = gravity.basis * Vector::new(x.x, 0.);
+            physics.gravity = physics.jump_gravity * physics.heavy_scalar;
+        }
+
+        let movement: Vector<f32> = if jump.buffered <= 0. {
+            continue;
+        } else if jump.air_time <= physics.coyote_time {
+            // Spend the coyote window on this jump
+            jump.air_time = f32::INFINITY;
+            [x.x, physics.jump_velocity].into()
+        } else if contacts.wall_left || contacts.wall_right {
+            // Push away from the wall along the local right axis
+            let away = if contacts.wall_left { 1. } else { -1. };
+            jump.wall_lock = physics.wall_jump_lock;
+            [away * physics.wall_jump_velocity, physics.jump_velocity].into()
+        } else {
+            continue;
+        };
+
+        physics.gravity = if actions.pressed(Action::Jump) {
+            physics.jump_gravity
+        } else {
+            // A buffered press that was already released gets a short hop
+            physics.jump_gravity * physics.heavy_scalar
+        };
+        velocity.linvel = gravity.basis * movement;
+        jump.buffered = 0.;
     }
 }
 
+type MoveQuery<'a> = (
+    &'a Physics,
+    &'a Gravity,
+    &'a Contacts,
+    &'a Jump,
+    &'a Dash,
+    &'a mut RigidBodyVelocity,
+);
+
+// Move towards the held direction along the local right axis. Speeding
+// up, slowing down and turning around each have their own rate, which
+// differ on the ground and in the air.
 pub fn player_move(
-    kb: Res<Input<KeyCode>>,
-    mut query: Query<(&Physics, &mut RigidBodyVelocity), With<Player>>,
+    time: Res<Time>,
+    actions: Res<Input<Action>>,
+    mut query: Query<MoveQuery, With<Player>>,
 ) {
-    for (physics, mut velocity) in query.iter_mut() {
-        let decomposition = physics.basis.lu();
+    for (physics, gravity, contacts, jump, dash, mut velocity) in
+        query.iter_mut()
+    {
+        if jump.wall_lock > 0. || dash.dashing() {
+            continue;
+        }
+
+        let mut input = 0.;
+        if actions.pressed(Action::MoveLeft) {
+            input -= 1.;
+        }
+        if actions.pressed(Action::MoveRight) {
+            input += 1.;
+        }
+
+        let (acceleration, deceleration, turn) = if contacts.grounded() {
+            (
+                physics.ground_acceleration,
+                physics.ground_deceleration,
+                physics.ground_turn,
+            )
+        } else {
+            (
+                physics.air_acceleration,
+                physics.air_deceleration,
+                physics.air_turn,
+            )
+        };
+
+        let decomposition = gravity.basis.lu();
         let x = decomposition.solve(&velocity.linvel).expect("Ooops!");
-        if kb.pressed(KeyCode::A) {
-            if x.x > -1. * physics.velocity {
-                let movement: Vector<f32> =
-                    [-1. * physics.velocity, x.y].into();
-                velocity.linvel = physics.basis * movement;
-            }
+        let target = input * physics.velocity;
+        let rate = if input * x.x < 0. {
+            turn
+        } else if x.x.abs() < target.abs() {
+            acceleration
+        } else {
+            // No input, or going faster than walking after a dash
+            deceleration
+        };
+
+        let change = (target - x.x)
+            .clamp(-rate * time.delta_seconds(), rate * time.delta_seconds());
+        let movement: Vector<f32> = [x.x + change, x.y].into();
+        velocity.linvel = gravity.basis * movement;
+    }
+}
+
+// Pressing into a wall while falling slows the fall down to
+// wall_slide_velocity.
+pub fn wall_slide(
+    actions: Res<Input<Action>>,
+    mut query: Query<
+        (&Physics, &Gravity, &Contacts, &Dash, &mut RigidBodyVelocity),
+        With<Player>,
+    >,
+) {
+    for (physics, gravity, contacts, dash, mut velocity) in query.iter_mut() {
+        if dash.dashing() {
+            continue;
         }
 
-        if kb.pressed(KeyCode::D) {
-            if x.x < physics.velocity {
-                let movement: Vector<f32> = [physics.velocity, x.y].into();
-                velocity.linvel = physics.basis * movement;
-            }
+        let pressing = (contacts.wall_left
+            && actions.pressed(Action::MoveLeft))
+            || (contacts.wall_right && actions.pressed(Action::MoveRight));
+        if !pressing || contacts.grounded() {
+            continue;
+        }
+
+        let decomposition = gravity.basis.lu();
+        let x = decomposition.solve(&velocity.linvel).expect("Ooops!");
+        if x.y < -physics.wall_slide_velocity {
+            let movement: Vector<f32> =
+                [x.x, -physics.wall_slide_velocity].into();
+            velocity.linvel = gravity.basis * movement;
         }
     }
 }
 
-pub fn respawn(mut query: Query<&mut RigidBodyPosition, With<Player>>) {
-    for mut position in query.iter_mut() {
-        let magnitude = position.position.translation.vector.magnitude();
+type SnapQuery<'a> = (
+    Entity,
+    &'a Physics,
+    &'a Gravity,
+    &'a Contacts,
+    &'a Jump,
+    &'a Dash,
+    &'a ColliderShape,
+    &'a mut RigidBodyPosition,
+);
+
+// Keep the player on the floor when walking down a slope or over the top
+// of one, instead of flying off it.
+pub fn snap_to_ground(
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    collider_types: Query<&ColliderType>,
+    mut query: Query<SnapQuery, (With<Player>, Without<Kinematic>)>,
+) {
+    let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (entity, physics, gravity, contacts, jump, dash, shape, mut position) in
+        query.iter_mut()
+    {
+        // Only just off the floor, and not because of a jump or a dash
+        if contacts.grounded() || jump.air_time > SNAP_TIME || dash.dashing() {
+            continue;
+        }
 
-        if magnitude > BOUNDARY {
-            position.position = [0., 0.].into();
+        let handle = entity.handle();
+        let solid =
+            |other: ColliderHandle| is_solid(handle, other, &collider_types);
+        let down = gravity.basis * Vector::new(0., -1.);
+        if let Some((_, toi)) = query_pipeline.cast_shape(
+            &colliders,
+            &position.position,
+            &down,
+            &**shape,
+            physics.snap_distance,
+            InteractionGroups::all(),
+            Some(&solid),
+        ) {
+            position.position.translation.vector += down * toi.toi;
         }
     }
 }
 
+// Standing still on a floor, gravity pulls into it so the player doesn't
+// slide down slopes.
+pub fn stick_to_slope(
+    actions: Res<Input<Action>>,
+    mut query: Query<(&Contacts, &Dash, &mut Gravity), With<Player>>,
+) {
+    let idle = !actions.pressed(Action::MoveLeft)
+        && !actions.pressed(Action::MoveRight);
+    for (contacts, dash, mut gravity) in query.iter_mut() {
+        gravity.floor_normal = if idle && !dash.dashing() {
+            contacts.floor_normal
+        } else {
+            None
+        };
+    }
+}
+
+// Put the player back at the start, resetting its physics to the ones the
+// start asks for.
+pub fn move_to_start(
+    mut events: EventReader<MoveToStart>,
+    start: Res<PlayerStart>,
+    tuning: Res<PlayerTuning>,
+    mut query: Query<
+        (
+            &mut RigidBodyPosition,
+            &mut RigidBodyVelocity,
+            &mut Physics,
+            &mut Gravity,
+        ),
+        With<Player>,
+    >,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for (mut position, mut velocity, mut physics, mut gravity) in
+        query.iter_mut()
+    {
+        position.position = start.position.into();
+        velocity.linvel = [0., 0.].into();
+        *physics = start.physics(&tuning);
+        *gravity = start.gravity(&tuning);
+    }
+}
+
 pub fn rotate(
-    kb: Res<Input<KeyCode>>,
-    mut query: Query<&mut Physics, With<Player>>,
+    actions: Res<Input<Action>>,
+    mut global: ResMut<GlobalGravity>,
+    mut query: Query<&mut Gravity, With<Player>>,
 ) {
-    for mut physics in query.iter_mut() {
-        if kb.just_pressed(KeyCode::Q) {
-            physics.rotate(TAU / -4.);
+    for mut gravity in query.iter_mut() {
+        let gravity = global.target(&mut gravity);
+        if actions.just_pressed(Action::RotateCW) {
+            gravity.rotate(TAU / -4.);
         }
 
-        if kb.just_pressed(KeyCode::E) {
-            physics.rotate(TAU / 4.);
+        if actions.just_pressed(Action::RotateCCW) {
+            gravity.rotate(TAU / 4.);
         }
     }
 }
 
-pub fn follow_player(
-    mut query: QuerySet<(
-        Query<&Transform, With<Player>>,
-        Query<&mut Transform, With<MainCamera>>,
-    )>,
+// Turn the player with its gravity so that down on screen matches the
+// gravity the player feels.
+pub fn align_player(
+    mut query: Query<(&Gravity, &mut RigidBodyPosition), With<Player>>,
 ) {
-    let mut translation: Vec3 = Vec3::ZERO;
-
-    if let Ok(transform) = query.q0().single() {
-        translation = transform.translation.to_owned();
+    for (gravity, mut position) in query.iter_mut() {
+        position.position.rotation = Rotation2::new(gravity.angle).into();
     }
+}
+
+// Shake the camera when the player hits the floor hard. The fall speed is
+// remembered from the last frame in the air, landing stops the body before
+// this sees it.
+pub fn hard_landing(
+    mut falling: Local<f32>,
+    mut shake: EventWriter<Shake>,
+    query: Query<(&Contacts, &Gravity, &RigidBodyVelocity), With<Player>>,
+) {
+    for (contacts, gravity, velocity) in query.iter() {
+        if !contacts.grounded() {
+            let local = gravity.basis.transpose() * velocity.linvel;
+            *falling = (-local.y).max(0.);
+            continue;
+        }
 
-    if let Ok(mut transform) = query.q1_mut().single_mut() {
-        translation.z = transform.translation.z.to_owned();
-        transform.translation = translation;
+        if *falling > HARD_LANDING_VELOCITY {
+            let hardness = *falling / HARD_LANDING_VELOCITY - 1.;
+            shake.send(Shake(hardness.min(1.) * HARD_LANDING_TRAUMA));
+        }
+        *falling = 0.;
     }
 }
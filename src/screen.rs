@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use bevy::render::texture::FilterMode;
+use bevy::window::{WindowCreated, WindowResized, WindowScaleFactorChanged};
+
+use crate::ldtk::TILE_SCALE;
+use crate::{MainCamera, METERS_TO_PIXELS, WINDOW_HEIGHT, WINDOW_WIDTH};
+
+// How much of the world the camera shows, in pixels, whatever the size of
+// the window
+pub const VIEW_WIDTH: f32 = WINDOW_WIDTH * METERS_TO_PIXELS;
+pub const VIEW_HEIGHT: f32 = WINDOW_HEIGHT * METERS_TO_PIXELS;
+
+// How many physical screen pixels one tileset pixel covers at zoom 1,
+// always a whole number
+pub struct PixelScale(pub f32);
+
+// The bars covering the parts of the window outside the view
+enum Letterbox {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+// Shows the same view in every window by scaling the tilesets' pixels up
+// by a whole number of screen pixels and letterboxing what's left over.
+pub struct ScreenPlugin;
+
+impl Plugin for ScreenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(PixelScale(1.))
+            .add_startup_system(spawn_letterbox.system())
+            .add_system(scale_view.system())
+            .add_system(crisp_textures.system());
+    }
+}
+
+fn spawn_letterbox(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let material = materials.add(Color::BLACK.into());
+    let sides = vec![
+        Letterbox::Left,
+        Letterbox::Right,
+        Letterbox::Top,
+        Letterbox::Bottom,
+    ];
+    for side in sides {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                material: material.clone(),
+                ..Default::default()
+            })
+            .insert(side);
+    }
+}
+
+// Pick the biggest whole number of screen pixels per tileset pixel that
+// fits the view in the window, and cover the rest with the letterbox.
+fn scale_view(
+    mut created: EventReader<WindowCreated>,
+    mut resized: EventReader<WindowResized>,
+    mut rescaled: EventReader<WindowScaleFactorChanged>,
+    windows: Res<Windows>,
+    mut pixel_scale: ResMut<PixelScale>,
+    mut cameras: Query<&mut OrthographicProjection, With<MainCamera>>,
+    mut bars: Query<(&Letterbox, &mut Style)>,
+) {
+    if created.iter().count() == 0
+        && resized.iter().count() == 0
+        && rescaled.iter().count() == 0
+    {
+        return;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let pixels_wide = VIEW_WIDTH / TILE_SCALE;
+    let pixels_high = VIEW_HEIGHT / TILE_SCALE;
+    let factor = (window.physical_width() as f32 / pixels_wide)
+        .min(window.physical_height() as f32 / pixels_high)
+        .floor()
+        .max(1.);
+    pixel_scale.0 = factor;
+
+    // The projection works in logical pixels, which can be several
+    // physical ones on high DPI screens
+    let scale = TILE_SCALE * window.scale_factor() as f32 / factor;
+    for mut projection in cameras.iter_mut() {
+        projection.scale = scale;
+    }
+
+    let bar_width = ((window.width() - VIEW_WIDTH / scale) / 2.).max(0.);
+    let bar_height = ((window.height() - VIEW_HEIGHT / scale) / 2.).max(0.);
+    for (side, mut style) in bars.iter_mut() {
+        let (position, width, height) = match side {
+            Letterbox::Left => (
+                Rect {
+                    left: Val::Px(0.),
+                    top: Val::Px(0.),
+                    ..Default::default()
+                },
+                Val::Px(bar_width),
+                Val::Percent(100.),
+            ),
+            Letterbox::Right => (
+                Rect {
+                    right: Val::Px(0.),
+                    top: Val::Px(0.),
+                    ..Default::default()
+                },
+                Val::Px(bar_width),
+                Val::Percent(100.),
+            ),
+            Letterbox::Top => (
+                Rect {
+                    left: Val::Px(0.),
+                    top: Val::Px(0.),
+                    ..Default::default()
+                },
+                Val::Percent(100.),
+                Val::Px(bar_height),
+            ),
+            Letterbox::Bottom => (
+                Rect {
+                    left: Val::Px(0.),
+                    bottom: Val::Px(0.),
+                    ..Default::default()
+                },
+                Val::Percent(100.),
+                Val::Px(bar_height),
+            ),
+        };
+        style.position = position;
+        style.size = Size::new(width, height);
+    }
+}
+
+// Sample textures without blending neighbouring pixels so that the scaled
+// up tiles keep hard edges.
+fn crisp_textures(
+    mut events: EventReader<AssetEvent<Texture>>,
+    mut textures: ResMut<Assets<Texture>>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } = event {
+            if let Some(texture) = textures.get_mut(handle) {
+                texture.sampler.min_filter = FilterMode::Nearest;
+                texture.sampler.mag_filter = FilterMode::Nearest;
+            }
+        }
+    }
+}
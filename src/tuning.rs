@@ -0,0 +1,252 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::contact::Contacts;
+use crate::gravity::{GlobalGravity, Gravity, DEFAULT_ROTATION_DURATION};
+use crate::kinematic::Kinematic;
+use crate::player::{
+    player_shape, Controller, Physics, Player, PlayerStart, DEFAULT_FRICTION,
+    DEFAULT_HEAVY_SCALAR, DEFAULT_JUMP_DISTANCE, DEFAULT_JUMP_HEIGHT,
+    DEFAULT_RESTITUTION, DEFAULT_VELOCITY, PLAYER_HEIGHT, PLAYER_WIDTH,
+};
+use crate::METERS_TO_PIXELS;
+
+pub const TUNING_FILE_PATH: &str = "player.tuning.ron";
+
+// How the player moves and feels (Units are meters and seconds). The
+// derived values (jump velocity and gravity) are computed from these by
+// Physics::new. Values set on the LDtk PlayerStart still win over these.
+//
+// A copy of the loaded asset is kept as a resource so systems don't need
+// to look it up, it falls back to the defaults until the file loads.
+#[derive(Clone, Deserialize, Serialize, TypeUuid)]
+#[uuid = "2d5b4c1e-7a0f-4f4e-9a59-3c1f6d0b8e21"]
+#[serde(default)]
+pub struct PlayerTuning {
+    pub velocity: f32,
+    pub jump_distance: f32,
+    pub jump_height: f32,
+    pub heavy_scalar: f32,
+    pub coyote_time: f32,
+    pub jump_buffer: f32,
+    pub ground_acceleration: f32,
+    pub ground_deceleration: f32,
+    pub ground_turn: f32,
+    pub air_acceleration: f32,
+    pub air_deceleration: f32,
+    pub air_turn: f32,
+    // Degrees from up of the steepest floor
+    pub max_slope: f32,
+    pub snap_distance: f32,
+    pub wall_slide_velocity: f32,
+    pub wall_jump_velocity: f32,
+    pub wall_jump_lock: f32,
+    pub dash_velocity: f32,
+    pub dash_duration: f32,
+    pub dash_cooldown: f32,
+    pub dash_charges: u32,
+    pub rotation_duration: f32,
+    pub step_offset: f32,
+    pub controller: Controller,
+
+    // Size of the player's collider and sprite
+    pub width: f32,
+    pub height: f32,
+    pub friction: f32,
+    pub restitution: f32,
+}
+
+impl Default for PlayerTuning {
+    fn default() -> Self {
+        let physics = Physics::new(
+            DEFAULT_VELOCITY,
+            DEFAULT_JUMP_DISTANCE,
+            DEFAULT_JUMP_HEIGHT,
+            DEFAULT_HEAVY_SCALAR,
+        );
+        PlayerTuning {
+            velocity: physics.velocity,
+            jump_distance: physics.jump_distance,
+            jump_height: physics.jump_height,
+            heavy_scalar: physics.heavy_scalar,
+            coyote_time: physics.coyote_time,
+            jump_buffer: physics.jump_buffer,
+            ground_acceleration: physics.ground_acceleration,
+            ground_deceleration: physics.ground_deceleration,
+            ground_turn: physics.ground_turn,
+            air_acceleration: physics.air_acceleration,
+            air_deceleration: physics.air_deceleration,
+            air_turn: physics.air_turn,
+            max_slope: physics.max_slope.to_degrees(),
+            snap_distance: physics.snap_distance,
+            wall_slide_velocity: physics.wall_slide_velocity,
+            wall_jump_velocity: physics.wall_jump_velocity,
+            wall_jump_lock: physics.wall_jump_lock,
+            dash_velocity: physics.dash_velocity,
+            dash_duration: physics.dash_duration,
+            dash_cooldown: physics.dash_cooldown,
+            dash_charges: physics.dash_charges,
+            rotation_duration: DEFAULT_ROTATION_DURATION,
+            step_offset: physics.step_offset,
+            controller: Controller::default(),
+            width: PLAYER_WIDTH,
+            height: PLAYER_HEIGHT,
+            friction: DEFAULT_FRICTION,
+            restitution: DEFAULT_RESTITUTION,
+        }
+    }
+}
+
+// Handle to the tuning file, kept so it stays loaded and is hot reloaded.
+// saved is set when the debug panel writes the file, the reload that
+// follows only updates the resource since the player already has the values.
+pub struct TuningHandle {
+    pub handle: Handle<PlayerTuning>,
+    pub saved: bool,
+}
+
+#[derive(Default)]
+pub struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let tuning: PlayerTuning = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(tuning));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<PlayerTuning>()
+            .init_asset_loader::<TuningLoader>()
+            .insert_resource(PlayerTuning::default())
+            .add_startup_system_to_stage(
+                StartupStage::PreStartup,
+                setup.system(),
+            )
+            .add_system(apply_tuning.system())
+            .add_system(switch_controller.system());
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(TuningHandle {
+        handle: asset_server.load(TUNING_FILE_PATH),
+        saved: false,
+    });
+}
+
+type TuningQuery<'a> = (
+    &'a mut Physics,
+    &'a mut Gravity,
+    &'a mut Contacts,
+    &'a mut ColliderShape,
+    &'a mut ColliderMaterial,
+    &'a mut Sprite,
+);
+
+// Copy the tuning into the resource whenever the file is loaded or edited
+// and apply it to the player without moving it or resetting its gravity.
+fn apply_tuning(
+    mut events: EventReader<AssetEvent<PlayerTuning>>,
+    mut handle: ResMut<TuningHandle>,
+    assets: Res<Assets<PlayerTuning>>,
+    mut tuning: ResMut<PlayerTuning>,
+    start: Res<PlayerStart>,
+    mut global: ResMut<GlobalGravity>,
+    mut query: Query<TuningQuery, With<Player>>,
+) {
+    let mut changed = false;
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: h }
+            | AssetEvent::Modified { handle: h } => {
+                changed |= *h == handle.handle;
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    if !changed {
+        return;
+    }
+
+    if let Some(loaded) = assets.get(&handle.handle) {
+        *tuning = loaded.clone();
+    }
+    global.gravity.rotation_duration = tuning.rotation_duration;
+
+    // Applying it again would put the level overrides back on top of the
+    // values that were just edited
+    if handle.saved {
+        handle.saved = false;
+        return;
+    }
+
+    for (
+        mut physics,
+        mut gravity,
+        mut contacts,
+        mut shape,
+        mut material,
+        mut sprite,
+    ) in query.iter_mut()
+    {
+        *physics = start.physics(&tuning);
+        gravity.rotation_duration = tuning.rotation_duration;
+
+        *shape = player_shape(tuning.width, tuning.height);
+        contacts.max_slope = physics.max_slope;
+        material.friction = tuning.friction;
+        material.restitution = tuning.restitution;
+        sprite.size = Vec2::new(
+            tuning.width * METERS_TO_PIXELS,
+            tuning.height * METERS_TO_PIXELS,
+        );
+    }
+}
+
+// Switch the player between the dynamic and kinematic controllers when the
+// tuning asks for the other one. The body keeps its place and velocity.
+fn switch_controller(
+    mut commands: Commands,
+    tuning: Res<PlayerTuning>,
+    mut query: Query<(Entity, &mut RigidBodyType), With<Player>>,
+) {
+    if !tuning.is_changed() {
+        return;
+    }
+
+    let body = tuning.controller.body_type();
+    for (entity, mut body_type) in query.iter_mut() {
+        if *body_type == body {
+            continue;
+        }
+
+        *body_type = body;
+        match tuning.controller {
+            Controller::Dynamic => {
+                commands.entity(entity).remove::<Kinematic>();
+            }
+            Controller::Kinematic => {
+                commands.entity(entity).insert(Kinematic);
+            }
+        }
+    }
+}